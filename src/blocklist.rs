@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config::BlocklistConfig,
+    dns_packet::DnsPacket,
+    dns_records::DnsRecord,
+    errors::{BlocklistError, BlocklistResult},
+    name::Name,
+    query_class::QueryClass,
+    query_type::QueryType,
+    result_code::ResultCode,
+};
+
+// Lists change, so the made up answers aren't cached for long
+const BLOCKED_TTL: u32 = 60;
+// How often the hits of the lists are logged, whether they are reloaded or not
+const HIT_LOG_INTERVAL: Duration = Duration::from_secs(60);
+// Hosts files map these to loopback, they are no ad servers
+const HOSTS_FILE_NAMES: [&str; 6] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+// AdBlock element hiding rules ("example.com##.banner"), they hide parts of pages and block nothing
+const COSMETIC_SEPARATORS: [&str; 4] = ["##", "#@#", "#?#", "#$#"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockAction {
+    Nxdomain,
+    Refused,
+    // 0.0.0.0 for A and :: for AAAA, like Pi-hole does
+    #[default]
+    Null,
+    // The addresses given as sinkhole
+    Sinkhole,
+}
+
+// Names of one list file and how many queries it blocked
+struct List {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    hits: AtomicU64,
+    // Hits at the time they were last logged
+    logged_hits: AtomicU64,
+}
+
+#[derive(Default)]
+struct Lists {
+    lists: Vec<List>,
+    // Blocked name to the list it came from, the first list naming it wins
+    blocked: HashMap<Name, usize>,
+    allowed: HashSet<Name>,
+    allowlist_modified: Vec<Option<SystemTime>>,
+}
+
+impl Lists {
+    fn load(config: &BlocklistConfig) -> BlocklistResult<Self> {
+        let mut lists = Lists::default();
+
+        for path in &config.allowlists {
+            let (modified, entries) = read_list(path)?;
+            // Exceptions of AdBlock lists allow as well
+            lists
+                .allowed
+                .extend(entries.blocked.into_iter().chain(entries.allowed));
+            lists.allowlist_modified.push(modified);
+        }
+
+        for (idx, path) in config.lists.iter().enumerate() {
+            let (modified, entries) = read_list(path)?;
+            log::info!(
+                "Loaded {} names from blocklist {:?}",
+                entries.blocked.len(),
+                path
+            );
+            for name in entries.blocked {
+                lists.blocked.entry(name).or_insert(idx);
+            }
+            lists.allowed.extend(entries.allowed);
+            lists.lists.push(List {
+                path: path.clone(),
+                modified,
+                hits: AtomicU64::new(0),
+                logged_hits: AtomicU64::new(0),
+            });
+        }
+
+        Ok(lists)
+    }
+
+    fn modified(config: &BlocklistConfig) -> Vec<Option<SystemTime>> {
+        config
+            .lists
+            .iter()
+            .chain(&config.allowlists)
+            .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+
+    // The name or one of its parents, allowed names win over blocked ones
+    fn find(&self, qname: &Name) -> Option<usize> {
+        let mut names = (0..qname.label_count()).map(|skip| qname.suffix(skip));
+        if names.clone().any(|name| self.allowed.contains(&name)) {
+            return None;
+        }
+        names.find_map(|name| self.blocked.get(&name).copied())
+    }
+}
+
+#[derive(Default)]
+struct ListEntries {
+    blocked: Vec<Name>,
+    // @@||domain^ exceptions of AdBlock lists
+    allowed: Vec<Name>,
+}
+
+fn read_list(path: &Path) -> BlocklistResult<(Option<SystemTime>, ListEntries)> {
+    let read_error = |error| BlocklistError::Read {
+        path: path.to_path_buf(),
+        error,
+    };
+    let modified = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(read_error)?;
+    let content = fs::read_to_string(path).map_err(read_error)?;
+
+    let mut entries = ListEntries::default();
+    let mut skipped = 0;
+    for line in content.lines() {
+        if !parse_line(line, &mut entries) {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        log::debug!("{:?} has {} lines that aren't domains", path, skipped);
+    }
+
+    Ok((Some(modified), entries))
+}
+
+// Hosts file lines ("0.0.0.0 ads.example.com"), plain domains and the domain rules of AdBlock
+// lists ("||ads.example.com^"). Returns false for lines none of them understands, e.g. AdBlock
+// rules about URLs or page elements
+fn parse_line(line: &str, entries: &mut ListEntries) -> bool {
+    let line = strip_comment(line).trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+        return true;
+    }
+    if COSMETIC_SEPARATORS
+        .iter()
+        .any(|separator| line.contains(separator))
+    {
+        return false;
+    }
+
+    if let Some(rule) = line.strip_prefix("@@||") {
+        return match adblock_domain(rule) {
+            Some(name) => {
+                entries.allowed.push(name);
+                true
+            }
+            None => false,
+        };
+    }
+    if let Some(rule) = line.strip_prefix("||") {
+        return match adblock_domain(rule) {
+            Some(name) => {
+                entries.blocked.push(name);
+                true
+            }
+            None => false,
+        };
+    }
+
+    let mut fields = line.split_whitespace();
+    let first = fields.next().unwrap_or_default();
+    if first.parse::<IpAddr>().is_ok() {
+        entries.blocked.extend(
+            fields
+                .filter(|host| !HOSTS_FILE_NAMES.contains(&host.to_ascii_lowercase().as_str()))
+                .filter_map(domain),
+        );
+        return true;
+    }
+
+    match (domain(first), fields.next()) {
+        (Some(name), None) => {
+            entries.blocked.push(name);
+            true
+        }
+        _ => false,
+    }
+}
+
+// A # starts a comment at the start of a line or after whitespace, elsewhere it is part of a rule
+fn strip_comment(line: &str) -> &str {
+    let start = line
+        .char_indices()
+        .find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)))
+        .map(|(i, _)| i);
+    start.map_or(line, |start| &line[..start])
+}
+
+// ||domain^ with no options, or only $important which means nothing to us
+fn adblock_domain(rule: &str) -> Option<Name> {
+    let (domain_part, options) = match rule.split_once('$') {
+        Some((domain_part, options)) => (domain_part, Some(options)),
+        None => (rule, None),
+    };
+    if options.is_some_and(|options| options != "important") {
+        return None;
+    }
+
+    domain(domain_part.strip_suffix('^')?)
+}
+
+fn domain(text: &str) -> Option<Name> {
+    let valid = !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'));
+    if !valid {
+        return None;
+    }
+
+    text.parse::<Name>().ok().filter(|name| !name.is_root())
+}
+
+// Blocked names get a made up answer instead of being resolved. The lists are read again when
+// their files change
+pub struct Blocklist {
+    config: BlocklistConfig,
+    lists: RwLock<Lists>,
+    hit_log_interval: Duration,
+}
+
+impl Blocklist {
+    pub fn load(config: &BlocklistConfig) -> BlocklistResult<Self> {
+        Ok(Self {
+            config: config.clone(),
+            lists: RwLock::new(Lists::load(config)?),
+            hit_log_interval: HIT_LOG_INTERVAL,
+        })
+    }
+
+    pub fn lookup(&self, qname: &Name, query_type: QueryType) -> Option<DnsPacket> {
+        let lists = self.lists.read().unwrap_or_else(|e| e.into_inner());
+        let list = &lists.lists[lists.find(qname)?];
+        list.hits.fetch_add(1, Ordering::Relaxed);
+        log::debug!("{} is blocked by {:?}", qname, list.path);
+
+        let mut packet = DnsPacket::default();
+        packet.header.rescode = ResultCode::NOERROR;
+
+        let addrs: Vec<IpAddr> = match self.config.action {
+            BlockAction::Nxdomain => {
+                packet.header.rescode = ResultCode::NXDOMAIN;
+                return Some(packet);
+            }
+            BlockAction::Refused => {
+                packet.header.rescode = ResultCode::REFUSED;
+                return Some(packet);
+            }
+            BlockAction::Null => vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ],
+            BlockAction::Sinkhole => self.config.sinkhole.clone(),
+        };
+
+        // Types other than A and AAAA, and families without a sinkhole address, get NODATA
+        packet.answers = addrs
+            .into_iter()
+            .filter_map(|addr| match (addr, query_type) {
+                (IpAddr::V4(addr), QueryType::A) => Some(DnsRecord::A {
+                    domain: qname.clone(),
+                    class: QueryClass::IN,
+                    addr,
+                    ttl: BLOCKED_TTL,
+                }),
+                (IpAddr::V6(addr), QueryType::AAAA) => Some(DnsRecord::AAAA {
+                    domain: qname.clone(),
+                    class: QueryClass::IN,
+                    addr,
+                    ttl: BLOCKED_TTL,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Some(packet)
+    }
+
+    // Logs the hits of every list and, unless reloading is turned off, checks the files for
+    // changes and reloads them. Runs forever
+    pub fn watch(&self) {
+        thread::scope(|scope| {
+            scope.spawn(|| loop {
+                thread::sleep(self.hit_log_interval);
+                self.log_hits();
+            });
+
+            if self.config.reload_interval_secs > 0 {
+                loop {
+                    thread::sleep(Duration::from_secs(self.config.reload_interval_secs));
+                    self.reload();
+                }
+            }
+        });
+    }
+
+    fn reload(&self) {
+        let modified = {
+            let lists = self.lists.read().unwrap_or_else(|e| e.into_inner());
+            lists
+                .lists
+                .iter()
+                .map(|list| list.modified)
+                .chain(lists.allowlist_modified.iter().copied())
+                .collect::<Vec<_>>()
+        };
+        if modified == Lists::modified(&self.config) {
+            return;
+        }
+
+        // A list that can't be read keeps the old ones in place
+        match Lists::load(&self.config) {
+            Ok(lists) => {
+                log::info!("Blocklists changed, reloaded them");
+                let mut current = self.lists.write().unwrap_or_else(|e| e.into_inner());
+                // Hits are counted on for the lists which are still there
+                for list in &lists.lists {
+                    if let Some(old) = current.lists.iter().find(|old| old.path == list.path) {
+                        list.hits
+                            .store(old.hits.load(Ordering::Relaxed), Ordering::Relaxed);
+                        list.logged_hits
+                            .store(old.logged_hits.load(Ordering::Relaxed), Ordering::Relaxed);
+                    }
+                }
+                *current = lists;
+            }
+            Err(e) => log::error!("Can't reload the blocklists - {}", e),
+        }
+    }
+
+    fn log_hits(&self) {
+        let lists = self.lists.read().unwrap_or_else(|e| e.into_inner());
+        for list in &lists.lists {
+            let hits = list.hits.load(Ordering::Relaxed);
+            if list.logged_hits.swap(hits, Ordering::Relaxed) != hits {
+                log::info!("Blocklist {:?} blocked {} queries", list.path, hits);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process, sync::Arc};
+
+    fn parse(lines: &[&str]) -> (Vec<bool>, ListEntries) {
+        let mut entries = ListEntries::default();
+        let parsed = lines
+            .iter()
+            .map(|line| parse_line(line, &mut entries))
+            .collect();
+        (parsed, entries)
+    }
+
+    fn names(names: &[&str]) -> Vec<Name> {
+        names.iter().map(|name| name.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_hosts_lines_and_adblock_domain_rules() {
+        let (parsed, entries) = parse(&[
+            "# hosts file",
+            "0.0.0.0 ads.example.com tracker.example.com # both of them",
+            "127.0.0.1 localhost",
+            "||banners.example.net^",
+            "@@||cdn.example.net^",
+            "plain.example.org",
+        ]);
+
+        assert_eq!(parsed, [true; 6]);
+        assert_eq!(
+            entries.blocked,
+            names(&[
+                "ads.example.com",
+                "tracker.example.com",
+                "banners.example.net",
+                "plain.example.org"
+            ])
+        );
+        assert_eq!(entries.allowed, names(&["cdn.example.net"]));
+    }
+
+    #[test]
+    fn rejects_cosmetic_and_option_rules() {
+        let (parsed, entries) = parse(&[
+            "example.com##.banner",
+            "example.com#@#.ad",
+            "example.com#?#div:has(> .ad)",
+            "example.com#$#body { overflow: auto }",
+            "||third.example.com^$third-party",
+        ]);
+
+        assert_eq!(parsed, [false; 5]);
+        assert!(entries.blocked.is_empty());
+        assert!(entries.allowed.is_empty());
+    }
+
+    #[test]
+    fn hits_are_logged_with_reloading_turned_off() {
+        let path = env::temp_dir().join(format!("swdns-blocklist-{}.txt", process::id()));
+        fs::write(&path, "ads.example.com\n").unwrap();
+        let mut blocklist = Blocklist::load(&BlocklistConfig {
+            lists: vec![path.clone()],
+            reload_interval_secs: 0,
+            ..BlocklistConfig::default()
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        blocklist.hit_log_interval = Duration::from_millis(10);
+
+        for qname in ["ads.example.com.", "www.ads.example.com.", "example.com."] {
+            blocklist.lookup(&qname.parse().unwrap(), QueryType::A);
+        }
+
+        let blocklist = Arc::new(blocklist);
+        let watched = Arc::clone(&blocklist);
+        thread::spawn(move || watched.watch());
+        thread::sleep(Duration::from_millis(200));
+
+        let lists = blocklist.lists.read().unwrap();
+        assert_eq!(lists.lists[0].hits.load(Ordering::Relaxed), 2);
+        assert_eq!(lists.lists[0].logged_hits.load(Ordering::Relaxed), 2);
+    }
+}
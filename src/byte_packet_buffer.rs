@@ -1,21 +1,48 @@
-use crate::errors::BytePacketBufferError;
+use std::collections::HashMap;
+
+use crate::{errors::BytePacketBufferError, name::Name};
 
 const MAX_JUMPS: usize = 5;
-const BUFFER_SIZE: usize = 512;
+// Classic udp message limit
+pub const BUFFER_SIZE: usize = 512;
+// Udp payload size we advertise and accept with EDNS(0)
+pub const MAX_UDP_PAYLOAD_SIZE: usize = 4096;
+// Largest message which can be framed by the 2 bytes tcp length prefix
+pub const MAX_MESSAGE_SIZE: usize = 65535;
 const JUMP_BITS: u8 = 0xC0;
+// Compression pointers have 14 bits for the offset
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
 
 pub type BytePacketBufferResult<T> = std::result::Result<T, BytePacketBufferError>;
 
 pub struct BytePacketBuffer {
-    pub buff: [u8; BUFFER_SIZE],
+    pub buff: Vec<u8>,
     pub pos: usize,
+    // Offsets of the names (and their suffixes) written so far, used for compression.
+    // Labels are matched byte for byte, so a pointer never changes the case of a name
+    names: HashMap<Vec<Vec<u8>>, usize>,
+    compress: bool,
 }
 
 impl BytePacketBuffer {
     pub fn new() -> Self {
+        Self::with_size(BUFFER_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
         BytePacketBuffer {
-            buff: [0; 512],
+            buff: vec![0; size],
             pos: 0,
+            names: HashMap::new(),
+            compress: true,
+        }
+    }
+
+    // Every name written in full, e.g. for the canonical form DNSSEC signs (RFC 4034 section 6.2)
+    pub fn uncompressed(size: usize) -> Self {
+        BytePacketBuffer {
+            compress: false,
+            ..Self::with_size(size)
         }
     }
 
@@ -27,7 +54,7 @@ impl BytePacketBuffer {
         self.pos += steps;
     }
 
-    fn seek(&mut self, pos: usize) {
+    pub fn seek(&mut self, pos: usize) {
         self.pos = pos;
     }
 
@@ -39,7 +66,7 @@ impl BytePacketBuffer {
     }
 
     pub fn get(&self, pos: usize) -> BytePacketBufferResult<u8> {
-        if self.pos >= 512 {
+        if pos >= self.buff.len() {
             return Err(BytePacketBufferError::PosOutOfRange);
         }
 
@@ -47,11 +74,11 @@ impl BytePacketBuffer {
     }
 
     fn get_range(&self, start: usize, len: usize) -> BytePacketBufferResult<&[u8]> {
-        if start + len > 512 {
+        if start + len > self.buff.len() {
             return Err(BytePacketBufferError::RangeOutOfTheBuffer);
         }
 
-        Ok(&self.buff[start..start + len as usize])
+        Ok(&self.buff[start..start + len])
     }
 
     pub fn read_u16(&mut self) -> BytePacketBufferResult<u16> {
@@ -74,12 +101,24 @@ impl BytePacketBuffer {
         Ok(res)
     }
 
-    pub fn read_qname(&mut self, outstr: &mut String) -> BytePacketBufferResult<()> {
+    pub fn read_bytes(&mut self, len: usize) -> BytePacketBufferResult<Vec<u8>> {
+        let res = self.get_range(self.pos, len)?.to_vec();
+        self.step(len);
+
+        Ok(res)
+    }
+
+    pub fn read_character_string(&mut self) -> BytePacketBufferResult<Vec<u8>> {
+        let len = self.read()?;
+        self.read_bytes(len as usize)
+    }
+
+    pub fn read_qname(&mut self) -> BytePacketBufferResult<Name> {
         let mut pos = self.pos;
         let mut jumped = false;
         let mut jumps_performed = 0;
 
-        let mut delim = "";
+        let mut labels = Vec::new();
 
         loop {
             if jumps_performed > MAX_JUMPS {
@@ -113,11 +152,8 @@ impl BytePacketBuffer {
                 break;
             }
 
-            outstr.push_str(delim);
-            let str_buffer = self.get_range(pos, len as usize)?;
-            outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
-
-            delim = ".";
+            // Bytes are kept as they are, case included
+            labels.push(self.get_range(pos, len as usize)?.to_vec());
 
             pos += len as usize;
         }
@@ -126,11 +162,11 @@ impl BytePacketBuffer {
             self.seek(pos);
         }
 
-        Ok(())
+        Ok(Name::from_labels(labels)?)
     }
 
     pub fn write_u8(&mut self, value: u8) -> BytePacketBufferResult<()> {
-        if self.pos >= 512 {
+        if self.pos >= self.buff.len() {
             return Err(BytePacketBufferError::PosOutOfRange);
         }
 
@@ -154,21 +190,53 @@ impl BytePacketBuffer {
         Ok(())
     }
 
-    pub fn write_qname(&mut self, qname: &str) -> BytePacketBufferResult<()> {
-        for label in qname.split('.') {
-            let len = label.len();
-            if len > 63 {
-                return Err(BytePacketBufferError::LabelLengthTooBig {
-                    length: len,
-                    label: label.into(),
-                    input: qname.into(),
-                });
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> BytePacketBufferResult<()> {
+        for b in bytes {
+            self.write_u8(*b)?;
+        }
+
+        Ok(())
+    }
+
+    // <character-string> from RFC 1035, a length byte followed by at most 255 bytes
+    pub fn write_character_string(&mut self, bytes: &[u8]) -> BytePacketBufferResult<()> {
+        if bytes.len() > 255 {
+            return Err(BytePacketBufferError::CharacterStringTooLong {
+                length: bytes.len(),
+            });
+        }
+
+        self.write_u8(bytes.len() as u8)?;
+        self.write_bytes(bytes)
+    }
+
+    pub fn write_qname(&mut self, qname: &Name) -> BytePacketBufferResult<()> {
+        self.write_name(qname, true)
+    }
+
+    // RDATA of the types defined after RFC 1035 must not be compressed (RFC 3597 section 4)
+    pub fn write_qname_uncompressed(&mut self, qname: &Name) -> BytePacketBufferResult<()> {
+        self.write_name(qname, false)
+    }
+
+    fn write_name(&mut self, qname: &Name, compress: bool) -> BytePacketBufferResult<()> {
+        for (idx, label) in qname.labels().iter().enumerate() {
+            let suffix = &qname.labels()[idx..];
+
+            if compress && self.compress {
+                if let Some(&offset) = self.names.get(suffix) {
+                    return self.write_u16(((JUMP_BITS as u16) << 8) | offset as u16);
+                }
             }
 
-            self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write_u8(*b)?;
+            // Uncompressed names may still be pointed to by the ones written later
+            if self.pos <= MAX_POINTER_OFFSET {
+                self.names.entry(suffix.to_vec()).or_insert(self.pos);
             }
+
+            // Name guarantees labels of at most 63 bytes
+            self.write_u8(label.len() as u8)?;
+            self.write_bytes(label)?;
         }
 
         self.write_u8(0)?; //equvialent for the C \0 char
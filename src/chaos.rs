@@ -0,0 +1,49 @@
+use crate::{
+    config::IdentityConfig, dns_packet::DnsPacket, dns_records::DnsRecord, name::Name,
+    query_class::QueryClass, query_type::QueryType, result_code::ResultCode,
+};
+
+// Answers the CHAOS class names which tell what is running behind the address (RFC 4892)
+pub struct ServerIdentity {
+    version: String,
+    id: String,
+}
+
+impl ServerIdentity {
+    pub fn from_config(config: &IdentityConfig) -> Self {
+        Self {
+            version: config.version.clone(),
+            id: config.id.clone(),
+        }
+    }
+
+    pub fn lookup(&self, qname: &Name, query_type: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::default();
+
+        let value = match qname.to_lowercase().to_string().as_str() {
+            "version.bind." | "version.server." => &self.version,
+            "hostname.bind." | "id.server." => &self.id,
+            _ => "",
+        };
+
+        // Unknown names and identities the operator doesn't want to tell
+        if value.is_empty() {
+            packet.header.rescode = ResultCode::REFUSED;
+            return packet;
+        }
+
+        packet.header.rescode = ResultCode::NOERROR;
+        packet.header.authoritative_answer = true;
+
+        if query_type == QueryType::TXT {
+            packet.answers.push(DnsRecord::TXT {
+                domain: qname.clone(),
+                class: QueryClass::CH,
+                data: value.as_bytes().chunks(255).map(<[u8]>::to_vec).collect(),
+                ttl: 0,
+            });
+        }
+
+        packet
+    }
+}
@@ -0,0 +1,661 @@
+use std::{
+    env, fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::{
+    blocklist::BlockAction,
+    dnssec,
+    errors::{ConfigError, ConfigResult},
+    forwarder::SelectionStrategy,
+    name::Name,
+    query_log::QueryLogFormat,
+    query_type::QueryType,
+    root_hints,
+    signer::SigningAlgorithm,
+};
+
+const USAGE: &str = "Usage: swdns [OPTIONS]
+
+Options:
+  --config <PATH>          TOML config file
+  --listen <ADDR>          address to listen on, may be repeated (e.g. 0.0.0.0:53, [::]:53)
+  --root-hint <IP>         root server to start recursion from, may be repeated
+  --root-hints-file <PATH> named.root file to take the root hints from
+  --ip-preference <P>      ipv4, ipv6, ipv4-only or ipv6-only, how name servers are reached
+  --workers <N>            udp workers per listen address
+  --max-in-flight <N>      recursions allowed to run at the same time
+  --max-tcp-connections <N> tcp clients served at the same time per listen address
+  --cache-size <N>         cached record sets
+  --negative-cache-size <N> cached NXDOMAIN/NODATA answers
+  --max-ttl <SECS>         longest time an answer is cached, whatever its ttl
+  --max-negative-ttl <SECS> longest time an NXDOMAIN/NODATA answer is cached
+  --timeout-ms <MS>        first upstream query timeout, doubles on every retry
+  --query-rounds <N>       how many times a silent name server is asked
+  --log-level <LEVEL>      off, error, warn, info, debug or trace
+  --no-recursion           answer only from the cache
+  --randomize-case         randomise the letter case of upstream queries (0x20)
+  --dnssec                 validate the answers of the recursion with DNSSEC
+  --trust-anchor-file <PATH> DS or DNSKEY records to validate from instead of the root KSKs
+  --mode <MODE>            recursive or forward
+  --forward <ADDR>         upstream resolver for the forward mode, may be repeated (e.g. 1.1.1.1, 1.1.1.1:53)
+  --forward-strategy <S>   round-robin or fastest
+  --version-string <S>     answer to version.bind CH TXT, empty refuses the query
+  --server-id <S>          answer to id.server CH TXT, empty refuses the query
+  --zone <ORIGIN=FILE>     serve a zone from a master file, may be repeated (e.g. corp.internal=corp.zone)
+  --blocklist <PATH>       hosts, domain or AdBlock list of names to block, may be repeated
+  --allowlist <PATH>       names which are never blocked, may be repeated
+  --block-action <A>       nxdomain, refused, null (0.0.0.0 and ::) or sinkhole
+  --sinkhole <IP>          address blocked names resolve to with the sinkhole action, may be repeated
+  --metrics-listen <ADDR>  serve Prometheus metrics over http on /metrics (e.g. 127.0.0.1:9153)
+  --query-log <PATH>       log every query to the file, - for stdout
+  --query-log-socket <ADDR> send the query log to a unix socket path or tcp host:port
+  --query-log-format <F>   text, json or dnstap
+  --generate-key <ZONE>    print a new signing key file for the zone and exit
+  --ksk                    make the generated key a KSK instead of a ZSK
+  --key-algorithm <ALG>    ecdsap256sha256 or ed25519, of the generated key
+  --help                   print this message";
+
+const VALUE_FLAGS: &[&str] = &[
+    "--config",
+    "--listen",
+    "--root-hint",
+    "--root-hints-file",
+    "--ip-preference",
+    "--workers",
+    "--max-in-flight",
+    "--max-tcp-connections",
+    "--cache-size",
+    "--negative-cache-size",
+    "--max-ttl",
+    "--max-negative-ttl",
+    "--timeout-ms",
+    "--query-rounds",
+    "--log-level",
+    "--mode",
+    "--forward",
+    "--forward-strategy",
+    "--zone",
+    "--version-string",
+    "--server-id",
+    "--trust-anchor-file",
+    "--blocklist",
+    "--allowlist",
+    "--block-action",
+    "--sinkhole",
+    "--metrics-listen",
+    "--query-log",
+    "--query-log-socket",
+    "--query-log-format",
+    "--generate-key",
+    "--key-algorithm",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Vec<SocketAddr>,
+    pub root_hints: Vec<IpAddr>,
+    // named.root, replaces root_hints when given
+    pub root_hints_file: Option<PathBuf>,
+    // Which address family name servers are reached over
+    pub ip_preference: IpPreference,
+    pub recursion: bool,
+    // 0x20, the upstream has to echo the question in the very same case
+    pub randomize_case: bool,
+    pub mode: ResolverMode,
+    pub forward: ForwardConfig,
+    // Zones we answer for ourselves, before any recursion or forwarding
+    pub zones: Vec<ZoneConfig>,
+    pub identity: IdentityConfig,
+    pub dnssec: DnssecConfig,
+    pub query_log: QueryLogConfig,
+    pub blocklist: BlocklistConfig,
+    // Address of the http listener with /metrics, none by default
+    pub metrics_listen: Option<SocketAddr>,
+    pub workers: usize,
+    pub max_in_flight: usize,
+    pub max_tcp_connections: usize,
+    pub cache: CacheConfig,
+    pub timeouts: TimeoutConfig,
+    #[serde(deserialize_with = "deserialize_level")]
+    pub log_level: LevelFilter,
+    #[serde(skip)]
+    pub generate_key: Option<KeyRequest>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpPreference {
+    // Both families, the preferred one is asked first
+    Ipv4,
+    Ipv6,
+    // Addresses of the other family are never used
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    pub fn allows(&self, addr: &IpAddr) -> bool {
+        match self {
+            IpPreference::Ipv4Only => addr.is_ipv4(),
+            IpPreference::Ipv6Only => addr.is_ipv6(),
+            IpPreference::Ipv4 | IpPreference::Ipv6 => true,
+        }
+    }
+
+    // Drops the addresses we may not use and moves the preferred family to the front
+    pub fn apply(&self, addrs: &mut Vec<SocketAddr>) {
+        let ipv4_first = matches!(self, IpPreference::Ipv4 | IpPreference::Ipv4Only);
+
+        addrs.retain(|addr| self.allows(&addr.ip()));
+        // Stable, the order within a family is kept
+        addrs.sort_by_key(|addr| addr.is_ipv4() != ipv4_first);
+    }
+
+    // Record types of the name server addresses, in the order they are looked up
+    pub fn address_types(&self) -> &'static [QueryType] {
+        match self {
+            IpPreference::Ipv4 => &[QueryType::A, QueryType::AAAA],
+            IpPreference::Ipv6 => &[QueryType::AAAA, QueryType::A],
+            IpPreference::Ipv4Only => &[QueryType::A],
+            IpPreference::Ipv6Only => &[QueryType::AAAA],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolverMode {
+    // Walk the tree from the root hints
+    Recursive,
+    // Ask the configured upstream resolvers and use their answers as is
+    Forward,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardConfig {
+    #[serde(deserialize_with = "deserialize_upstreams")]
+    pub upstreams: Vec<SocketAddr>,
+    pub strategy: SelectionStrategy,
+    // Applied in both modes, e.g. corp.internal to the office resolver
+    pub rules: Vec<ForwardRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardRule {
+    #[serde(deserialize_with = "deserialize_name")]
+    pub domain: Name,
+    #[serde(deserialize_with = "deserialize_upstreams")]
+    pub upstreams: Vec<SocketAddr>,
+    pub strategy: Option<SelectionStrategy>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    #[serde(deserialize_with = "deserialize_name")]
+    pub origin: Name,
+    // RFC 1035 master file
+    pub file: PathBuf,
+    // Key files the answers are signed with, see --generate-key
+    #[serde(default)]
+    pub keys: Vec<PathBuf>,
+    // Deny with NSEC3 instead of NSEC, no salt and no extra iterations (RFC 9276)
+    #[serde(default)]
+    pub nsec3: bool,
+}
+
+// --generate-key, print a new key file for the zone and exit
+#[derive(Debug, Clone)]
+pub struct KeyRequest {
+    pub zone: Name,
+    pub ksk: bool,
+    pub algorithm: SigningAlgorithm,
+}
+
+// What the server tells about itself over the CHAOS class, an empty value refuses the query
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub version: String,
+    pub id: String,
+}
+
+// Names answered with a made up answer instead of being resolved, off without lists
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlocklistConfig {
+    // hosts files, plain domains or AdBlock ||domain^ rules, a name blocks its subdomains too
+    pub lists: Vec<PathBuf>,
+    // Same formats, these names and their subdomains are never blocked
+    pub allowlists: Vec<PathBuf>,
+    pub action: BlockAction,
+    // Answers of the sinkhole action, A and AAAA get the addresses of their family
+    pub sinkhole: Vec<IpAddr>,
+    // How often the files are checked for changes, 0 never. Hits are logged every minute either way
+    pub reload_interval_secs: u64,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            lists: Vec::new(),
+            allowlists: Vec::new(),
+            action: BlockAction::default(),
+            sinkhole: Vec::new(),
+            reload_interval_secs: 60,
+        }
+    }
+}
+
+// One entry per answered query, off unless a file or a socket is given
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLogConfig {
+    pub format: QueryLogFormat,
+    // "-" is stdout
+    pub file: Option<PathBuf>,
+    // Unix socket path or host:port, e.g. of a dnstap collector
+    pub socket: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnssecConfig {
+    // Only answers of the recursion are validated, forwarded ones are taken as they come
+    pub validate: bool,
+    // DS or DNSKEY records in master file format
+    pub trust_anchors: Vec<String>,
+    // Replaces trust_anchors when given
+    pub trust_anchor_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub negative_size: usize,
+    // Upper bounds of the ttls taken from upstream, for answers and for NXDOMAIN/NODATA
+    pub max_ttl: u32,
+    pub max_negative_ttl: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub initial_ms: u64,
+    pub query_rounds: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], 2053))],
+            root_hints: root_hints::builtin(),
+            root_hints_file: None,
+            ip_preference: IpPreference::Ipv4,
+            recursion: true,
+            randomize_case: false,
+            mode: ResolverMode::Recursive,
+            forward: ForwardConfig::default(),
+            zones: Vec::new(),
+            identity: IdentityConfig::default(),
+            dnssec: DnssecConfig::default(),
+            query_log: QueryLogConfig::default(),
+            blocklist: BlocklistConfig::default(),
+            metrics_listen: None,
+            workers: 8,
+            max_in_flight: 128,
+            max_tcp_connections: 256,
+            cache: CacheConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            log_level: LevelFilter::Info,
+            generate_key: None,
+        }
+    }
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            upstreams: Vec::new(),
+            strategy: SelectionStrategy::RoundRobin,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            version: format!("swdns {}", env!("CARGO_PKG_VERSION")),
+            id: String::new(),
+        }
+    }
+}
+
+impl Default for DnssecConfig {
+    fn default() -> Self {
+        Self {
+            validate: false,
+            trust_anchors: dnssec::default_trust_anchors(),
+            trust_anchor_file: None,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            size: 10_000,
+            negative_size: 2_000,
+            max_ttl: 86_400,
+            // RFC 2308 section 5 suggests one to three hours
+            max_negative_ttl: 10_800,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            initial_ms: 800,
+            query_rounds: 3,
+        }
+    }
+}
+
+fn deserialize_level<'de, D>(deserializer: D) -> Result<LevelFilter, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    LevelFilter::from_str(&value).map_err(serde::de::Error::custom)
+}
+
+// Upstreams may be given without a port, 53 is used then
+fn parse_upstream(value: &str) -> Option<SocketAddr> {
+    value
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(|ip| (ip, 53).into()))
+}
+
+fn deserialize_upstreams<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| {
+            parse_upstream(value).ok_or_else(|| {
+                serde::de::Error::custom(format!("invalid upstream address {}", value))
+            })
+        })
+        .collect()
+}
+
+fn deserialize_name<'de, D>(deserializer: D) -> Result<Name, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    value
+        .parse()
+        .map_err(|error| serde::de::Error::custom(format!("invalid name {} - {}", value, error)))
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> ConfigResult<Self> {
+        let content = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        toml::from_str(&content).map_err(|error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    // Loads the file given with --config (if any) and applies the rest of the flags on top of it
+    pub fn from_args() -> ConfigResult<Self> {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let mut config = match flag_values(&args, "--config")?.last() {
+            Some(path) => Self::from_file(&PathBuf::from(path))?,
+            None => Self::default(),
+        };
+
+        config.apply_args(&args)?;
+        if let Some(path) = &config.root_hints_file {
+            config.root_hints =
+                root_hints::load(path).map_err(|error| ConfigError::RootHints { error })?;
+        }
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> ConfigResult<()> {
+        if self.mode == ResolverMode::Recursive
+            && !self
+                .root_hints
+                .iter()
+                .any(|addr| self.ip_preference.allows(addr))
+        {
+            return Err(ConfigError::Invalid {
+                reason: format!(
+                    "none of the root hints can be used with ip_preference {:?}",
+                    self.ip_preference
+                ),
+            });
+        }
+
+        if self.mode == ResolverMode::Forward && self.forward.upstreams.is_empty() {
+            return Err(ConfigError::Invalid {
+                reason: "forward mode needs at least one upstream".to_string(),
+            });
+        }
+
+        if self.blocklist.action == BlockAction::Sinkhole && self.blocklist.sinkhole.is_empty() {
+            return Err(ConfigError::Invalid {
+                reason: "the sinkhole block action needs sinkhole addresses".to_string(),
+            });
+        }
+
+        if self.query_log.file.is_some() && self.query_log.socket.is_some() {
+            return Err(ConfigError::Invalid {
+                reason: "the query log goes either to a file or to a socket".to_string(),
+            });
+        }
+
+        if let Some(rule) = self
+            .forward
+            .rules
+            .iter()
+            .find(|rule| rule.upstreams.is_empty())
+        {
+            return Err(ConfigError::Invalid {
+                reason: format!("forward rule for {} has no upstreams", rule.domain),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &[String]) -> ConfigResult<()> {
+        let mut iter = args.iter();
+
+        // Repeated list flags replace what the file says instead of extending it
+        let mut listen = Vec::new();
+        let mut root_hints = Vec::new();
+        let mut upstreams = Vec::new();
+        let mut zones = Vec::new();
+        let mut blocklists = Vec::new();
+        let mut allowlists = Vec::new();
+        let mut sinkhole = Vec::new();
+        let mut key_zone = None;
+        let mut ksk = false;
+        let mut key_algorithm = SigningAlgorithm::Ecdsap256sha256;
+
+        while let Some(flag) = iter.next() {
+            if flag == "--no-recursion" {
+                self.recursion = false;
+                continue;
+            }
+            if flag == "--randomize-case" {
+                self.randomize_case = true;
+                continue;
+            }
+            if flag == "--dnssec" {
+                self.dnssec.validate = true;
+                continue;
+            }
+            if flag == "--ksk" {
+                ksk = true;
+                continue;
+            }
+
+            if !VALUE_FLAGS.contains(&flag.as_str()) {
+                return Err(ConfigError::UnknownFlag {
+                    flag: flag.clone(),
+                    usage: USAGE,
+                });
+            }
+
+            let value = iter
+                .next()
+                .ok_or_else(|| ConfigError::MissingValue { flag: flag.clone() })?;
+
+            match flag.as_str() {
+                "--listen" => listen.push(parse_value(flag, value)?),
+                "--root-hint" => root_hints.push(parse_value(flag, value)?),
+                "--root-hints-file" => self.root_hints_file = Some(PathBuf::from(value)),
+                "--ip-preference" => self.ip_preference = parse_enum(flag, value)?,
+                "--workers" => self.workers = parse_value(flag, value)?,
+                "--max-in-flight" => self.max_in_flight = parse_value(flag, value)?,
+                "--max-tcp-connections" => self.max_tcp_connections = parse_value(flag, value)?,
+                "--cache-size" => self.cache.size = parse_value(flag, value)?,
+                "--negative-cache-size" => self.cache.negative_size = parse_value(flag, value)?,
+                "--max-ttl" => self.cache.max_ttl = parse_value(flag, value)?,
+                "--max-negative-ttl" => self.cache.max_negative_ttl = parse_value(flag, value)?,
+                "--timeout-ms" => self.timeouts.initial_ms = parse_value(flag, value)?,
+                "--query-rounds" => self.timeouts.query_rounds = parse_value(flag, value)?,
+                "--log-level" => self.log_level = parse_value(flag, value)?,
+                "--mode" => self.mode = parse_enum(flag, value)?,
+                "--forward" => upstreams.push(parse_upstream(value).ok_or_else(|| {
+                    ConfigError::InvalidValue {
+                        flag: flag.clone(),
+                        value: value.clone(),
+                    }
+                })?),
+                "--forward-strategy" => self.forward.strategy = parse_enum(flag, value)?,
+                "--version-string" => self.identity.version = value.clone(),
+                "--server-id" => self.identity.id = value.clone(),
+                "--trust-anchor-file" => self.dnssec.trust_anchor_file = Some(PathBuf::from(value)),
+                "--blocklist" => blocklists.push(PathBuf::from(value)),
+                "--allowlist" => allowlists.push(PathBuf::from(value)),
+                "--block-action" => self.blocklist.action = parse_enum(flag, value)?,
+                "--sinkhole" => sinkhole.push(parse_value(flag, value)?),
+                "--metrics-listen" => self.metrics_listen = Some(parse_value(flag, value)?),
+                "--query-log" => self.query_log.file = Some(PathBuf::from(value)),
+                "--query-log-socket" => self.query_log.socket = Some(value.clone()),
+                "--query-log-format" => self.query_log.format = parse_enum(flag, value)?,
+                "--generate-key" => key_zone = Some(parse_value(flag, value)?),
+                "--key-algorithm" => key_algorithm = parse_enum(flag, value)?,
+                "--zone" => {
+                    let (origin, file) =
+                        value
+                            .split_once('=')
+                            .ok_or_else(|| ConfigError::InvalidValue {
+                                flag: flag.clone(),
+                                value: value.clone(),
+                            })?;
+
+                    zones.push(ZoneConfig {
+                        origin: parse_value(flag, origin)?,
+                        file: PathBuf::from(file),
+                        keys: Vec::new(),
+                        nsec3: false,
+                    });
+                }
+                // --config is already loaded by now
+                _ => {}
+            }
+        }
+
+        if !listen.is_empty() {
+            self.listen = listen;
+        }
+        if !root_hints.is_empty() {
+            self.root_hints = root_hints;
+        }
+        if !upstreams.is_empty() {
+            self.forward.upstreams = upstreams;
+        }
+        if !zones.is_empty() {
+            self.zones = zones;
+        }
+        if !blocklists.is_empty() {
+            self.blocklist.lists = blocklists;
+        }
+        if !allowlists.is_empty() {
+            self.blocklist.allowlists = allowlists;
+        }
+        if !sinkhole.is_empty() {
+            self.blocklist.sinkhole = sinkhole;
+        }
+        self.generate_key = key_zone.map(|zone| KeyRequest {
+            zone,
+            ksk,
+            algorithm: key_algorithm,
+        });
+
+        Ok(())
+    }
+}
+
+fn flag_values<'a>(args: &'a [String], name: &str) -> ConfigResult<Vec<&'a String>> {
+    let mut values = Vec::new();
+
+    for (idx, arg) in args.iter().enumerate() {
+        if arg == name {
+            let value = args.get(idx + 1).ok_or_else(|| ConfigError::MissingValue {
+                flag: name.to_string(),
+            })?;
+            values.push(value);
+        }
+    }
+
+    Ok(values)
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> ConfigResult<T> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    })
+}
+
+// Enum flags are spelled the same way as in the config file
+fn parse_enum<T: serde::de::DeserializeOwned>(flag: &str, value: &str) -> ConfigResult<T> {
+    T::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value))
+        .map_err(|_| ConfigError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        })
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::CacheConfig, dns_records::DnsRecord, name::Name, query_type::QueryType,
+    result_code::ResultCode,
+};
+
+type CacheKey = (Name, QueryType);
+
+struct CacheEntry {
+    records: Vec<DnsRecord>,
+    stored_at: Instant,
+    // the smallest ttl of the set, the whole set expires together
+    ttl: u32,
+    // DNSSEC validated
+    secure: bool,
+}
+
+impl CacheEntry {
+    fn remaining_ttl(&self, now: Instant) -> Option<u32> {
+        let elapsed = now.duration_since(self.stored_at);
+        let lifetime = Duration::from_secs(self.ttl as u64);
+
+        if elapsed >= lifetime {
+            return None;
+        }
+
+        Some(self.ttl - elapsed.as_secs() as u32)
+    }
+
+    fn expires_at(&self) -> Instant {
+        self.stored_at + Duration::from_secs(self.ttl as u64)
+    }
+}
+
+// Entries along with the order they expire in, so the next one to push out is found without a
+// scan of the whole map. The sequence number keeps entries expiring at the same instant apart
+struct ExpiringEntries<K> {
+    entries: HashMap<K, ((Instant, u64), CacheEntry)>,
+    expiry: BTreeMap<(Instant, u64), K>,
+    sequence: u64,
+}
+
+impl<K> ExpiringEntries<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            expiry: BTreeMap::new(),
+            sequence: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn get(&self, key: &K) -> Option<&CacheEntry> {
+        self.entries.get(key).map(|(_, entry)| entry)
+    }
+
+    fn insert(&mut self, key: K, entry: CacheEntry) {
+        self.sequence += 1;
+        let expiry = (entry.expires_at(), self.sequence);
+
+        if let Some((old, _)) = self.entries.insert(key.clone(), (expiry, entry)) {
+            self.expiry.remove(&old);
+        }
+        self.expiry.insert(expiry, key);
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((expiry, _)) = self.entries.remove(key) {
+            self.expiry.remove(&expiry);
+        }
+    }
+
+    // Frees a slot when the map is full by pushing out the entry closest to expiring, which is an
+    // expired one if there is any. Returns false when nothing can be stored at all
+    fn make_room(&mut self, limit: usize) -> bool {
+        if limit == 0 {
+            return false;
+        }
+
+        while self.entries.len() >= limit {
+            let Some((_, key)) = self.expiry.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+        }
+
+        true
+    }
+}
+
+pub struct DnsCache {
+    entries: ExpiringEntries<CacheKey>,
+    // Negative answers (RFC 2308), the entry keeps the SOA that has to be sent in the authority section
+    // along with the NSEC records proving the answer
+    nxdomains: ExpiringEntries<Name>,
+    nodata: ExpiringEntries<CacheKey>,
+    size: usize,
+    // applies to NXDOMAIN and NODATA entries separately
+    negative_size: usize,
+    // Longer ttls are cut down, a broken or hostile server can't pin its records for years
+    max_ttl: u32,
+    max_negative_ttl: u32,
+}
+
+impl DnsCache {
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self {
+            entries: ExpiringEntries::new(),
+            nxdomains: ExpiringEntries::new(),
+            nodata: ExpiringEntries::new(),
+            size: config.size,
+            negative_size: config.negative_size,
+            max_ttl: config.max_ttl,
+            max_negative_ttl: config.max_negative_ttl,
+        }
+    }
+
+    // Expired entries count until they are looked up or pushed out
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn negative_len(&self) -> usize {
+        self.nxdomains.len() + self.nodata.len()
+    }
+
+    pub fn lookup(&mut self, qname: &Name, query_type: QueryType) -> Option<Vec<DnsRecord>> {
+        self.lookup_entry(qname, query_type)
+            .map(|(records, _)| records)
+    }
+
+    // The records together with their RRSIGs, and whether they were validated
+    pub fn lookup_entry(
+        &mut self,
+        qname: &Name,
+        query_type: QueryType,
+    ) -> Option<(Vec<DnsRecord>, bool)> {
+        let key = (qname.clone(), query_type);
+
+        Self::get_alive(&mut self.entries, &key)
+    }
+
+    // Returns NXDOMAIN or NOERROR (NODATA) along with the authority records, SOA first, if the name
+    // is cached as negative
+    pub fn lookup_negative(
+        &mut self,
+        qname: &Name,
+        query_type: QueryType,
+    ) -> Option<(ResultCode, Vec<DnsRecord>, bool)> {
+        if let Some((records, secure)) = Self::get_alive(&mut self.nxdomains, qname) {
+            return Some((ResultCode::NXDOMAIN, records, secure));
+        }
+
+        let key = (qname.clone(), query_type);
+        if let Some((records, secure)) = Self::get_alive(&mut self.nodata, &key) {
+            return Some((ResultCode::NOERROR, records, secure));
+        }
+
+        None
+    }
+
+    fn get_alive<K>(entries: &mut ExpiringEntries<K>, key: &K) -> Option<(Vec<DnsRecord>, bool)>
+    where
+        K: Hash + Eq + Clone,
+    {
+        let remaining = entries.get(key)?.remaining_ttl(Instant::now());
+
+        let Some(remaining) = remaining else {
+            entries.remove(key);
+            return None;
+        };
+
+        let entry = entries.get(key)?;
+        let records = entry
+            .records
+            .iter()
+            .cloned()
+            .map(|mut record| {
+                // Clients should see how long the record will still live, not the original ttl
+                record.set_ttl(remaining);
+                record
+            })
+            .collect();
+
+        Some((records, entry.secure))
+    }
+
+    pub fn insert(&mut self, records: &[DnsRecord], secure: bool) {
+        let mut sets: HashMap<CacheKey, Vec<DnsRecord>> = HashMap::new();
+
+        for record in records {
+            // ttl 0 means the record must be used only for the current transaction
+            if record.ttl() == 0 {
+                continue;
+            }
+
+            // Signatures are kept with the set they cover
+            let query_type = match record {
+                DnsRecord::RRSIG { type_covered, .. } => *type_covered,
+                _ => record.query_type(),
+            };
+            sets.entry((record.domain().clone(), query_type))
+                .or_default()
+                .push(record.clone());
+        }
+
+        let now = Instant::now();
+        for (key, records) in sets {
+            let ttl = records
+                .iter()
+                .map(DnsRecord::ttl)
+                .min()
+                .unwrap_or(0)
+                .min(self.max_ttl);
+
+            // e.g. the unsigned NS of a referral don't replace the validated ones of the zone
+            if !secure
+                && self
+                    .entries
+                    .get(&key)
+                    .is_some_and(|entry| entry.secure && entry.remaining_ttl(now).is_some())
+            {
+                continue;
+            }
+
+            if !self.entries.contains_key(&key) && !self.entries.make_room(self.size) {
+                return;
+            }
+
+            self.entries.insert(
+                key,
+                CacheEntry {
+                    records,
+                    stored_at: now,
+                    ttl,
+                    secure,
+                },
+            );
+        }
+    }
+
+    pub fn insert_nxdomain(&mut self, qname: &Name, authorities: &[DnsRecord], secure: bool) {
+        // Refreshing an entry doesn't push out another one
+        if !self.nxdomains.contains_key(qname) && !self.nxdomains.make_room(self.negative_size) {
+            return;
+        }
+
+        if let Some(entry) = self.negative_entry(authorities, secure) {
+            self.nxdomains.insert(qname.clone(), entry);
+        }
+    }
+
+    pub fn insert_nodata(
+        &mut self,
+        qname: &Name,
+        query_type: QueryType,
+        authorities: &[DnsRecord],
+        secure: bool,
+    ) {
+        let key = (qname.clone(), query_type);
+        if !self.nodata.contains_key(&key) && !self.nodata.make_room(self.negative_size) {
+            return;
+        }
+
+        if let Some(entry) = self.negative_entry(authorities, secure) {
+            self.nodata.insert(key, entry);
+        }
+    }
+
+    fn negative_entry(&self, authorities: &[DnsRecord], secure: bool) -> Option<CacheEntry> {
+        let soa = authorities
+            .iter()
+            .find(|record| matches!(record, DnsRecord::SOA { .. }))?;
+        let DnsRecord::SOA { ttl, minimum, .. } = soa else {
+            return None;
+        };
+
+        // RFC 2308 section 5, negative ttl is the minimum of the SOA ttl and the SOA MINIMUM field
+        let ttl = (*ttl).min(*minimum).min(self.max_negative_ttl);
+        if ttl == 0 {
+            return None;
+        }
+
+        let proof = authorities.iter().filter(|record| {
+            matches!(
+                record,
+                DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. } | DnsRecord::RRSIG { .. }
+            )
+        });
+
+        Some(CacheEntry {
+            records: [soa].into_iter().chain(proof).cloned().collect(),
+            stored_at: Instant::now(),
+            ttl,
+            secure,
+        })
+    }
+
+    // Walks from the qname up to the root and returns the closest zone whose name servers'
+    // addresses we know. The root is there once it has been primed
+    pub fn closest_name_servers(&mut self, qname: &Name) -> Option<(Name, Vec<IpAddr>)> {
+        let mut zone = qname.clone();
+
+        loop {
+            let hosts: Vec<Name> = self
+                .lookup(&zone, QueryType::NS)
+                .into_iter()
+                .flatten()
+                .filter_map(|record| match record {
+                    DnsRecord::NS { host, .. } => Some(host),
+                    _ => None,
+                })
+                .collect();
+
+            let addrs: Vec<IpAddr> = hosts
+                .iter()
+                .flat_map(|host| {
+                    [QueryType::A, QueryType::AAAA]
+                        .into_iter()
+                        .flat_map(|query_type| self.lookup(host, query_type).into_iter().flatten())
+                        .collect::<Vec<DnsRecord>>()
+                })
+                .filter_map(|record| match record {
+                    DnsRecord::A { addr, .. } => Some(IpAddr::V4(addr)),
+                    DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                    _ => None,
+                })
+                .collect();
+
+            if !addrs.is_empty() {
+                return Some((zone, addrs));
+            }
+
+            match zone.parent() {
+                Some(parent) => zone = parent,
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_class::QueryClass;
+    use std::net::Ipv4Addr;
+
+    fn name(name: &str) -> Name {
+        name.parse().unwrap()
+    }
+
+    fn cache() -> DnsCache {
+        DnsCache::from_config(&CacheConfig {
+            max_ttl: 3600,
+            max_negative_ttl: 300,
+            ..CacheConfig::default()
+        })
+    }
+
+    #[test]
+    fn ttls_are_cut_down_to_the_max_ttl() {
+        let mut cache = cache();
+        cache.insert(
+            &[DnsRecord::A {
+                domain: name("www.example."),
+                class: QueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: u32::MAX,
+            }],
+            false,
+        );
+
+        let records = cache.lookup(&name("www.example."), QueryType::A).unwrap();
+        assert_eq!(records[0].ttl(), 3600);
+    }
+
+    #[test]
+    fn negative_ttls_are_cut_down_to_the_max_negative_ttl() {
+        let mut cache = cache();
+        let soa = [DnsRecord::SOA {
+            domain: name("example."),
+            class: QueryClass::IN,
+            m_name: name("ns1.example."),
+            r_name: name("hostmaster.example."),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: u32::MAX,
+            ttl: u32::MAX,
+        }];
+        cache.insert_nxdomain(&name("nx.example."), &soa, false);
+        cache.insert_nodata(&name("www.example."), QueryType::MX, &soa, false);
+
+        for (qname, query_type) in [
+            ("nx.example.", QueryType::A),
+            ("www.example.", QueryType::MX),
+        ] {
+            let (_, records, _) = cache.lookup_negative(&name(qname), query_type).unwrap();
+            assert_eq!(records[0].ttl(), 300);
+        }
+    }
+}
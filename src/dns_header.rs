@@ -65,7 +65,7 @@ impl DnsHeader {
             (self.recursion_desired as u8)
                 | ((self.truncated_message as u8) << 1)
                 | ((self.authoritative_answer as u8) << 2)
-                | ((self.opcode as u8) << 3)
+                | (self.opcode << 3)
                 | ((self.response as u8) << 7),
         )?;
 
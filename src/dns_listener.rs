@@ -0,0 +1,173 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    time::{Duration, SystemTime},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    byte_packet_buffer::{
+        BytePacketBuffer, BytePacketBufferResult, MAX_MESSAGE_SIZE, MAX_UDP_PAYLOAD_SIZE,
+    },
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_records::DnsRecord,
+    dns_server::DnsServer,
+    errors::DnsServerResult,
+    query_log::{self, LoggedQuery, Transport},
+};
+
+// How long an idle tcp client may keep the connection open between queries
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const TCP_BACKLOG: i32 = 128;
+
+// IPv6 sockets take only IPv6 traffic, so [::] and 0.0.0.0 can be listened on side by side
+fn bind_socket(addr: SocketAddr, socket_type: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), socket_type, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+
+    Ok(socket)
+}
+
+pub fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    Ok(bind_socket(addr, Type::DGRAM, Protocol::UDP)?.into())
+}
+
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = bind_socket(addr, Type::STREAM, Protocol::TCP)?;
+    // Restarts don't have to wait for the old connections to leave TIME_WAIT
+    socket.set_reuse_address(true)?;
+    socket.listen(TCP_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+pub fn handle_udp_query(server: &DnsServer, socket: &UdpSocket) -> DnsServerResult<()> {
+    let mut req_buffer = BytePacketBuffer::with_size(MAX_UDP_PAYLOAD_SIZE);
+
+    let (len, src) = socket.recv_from(&mut req_buffer.buff)?;
+    let received = SystemTime::now();
+
+    let request = DnsPacket::from_buffer(&mut req_buffer)?;
+    let max_size = request.max_udp_payload_size();
+    let question = begin_log(server, &request);
+
+    let mut packet = server.handle_query(request);
+
+    let mut res_buffer = BytePacketBuffer::with_size(max_size);
+    write_truncated(&mut packet, &mut res_buffer)?;
+
+    socket.send_to(&res_buffer.buff[0..res_buffer.pos], src)?;
+
+    log_query(
+        server,
+        LoggedQuery {
+            received,
+            answered: SystemTime::now(),
+            client: src,
+            transport: Transport::Udp,
+            question: question.as_ref(),
+            rcode: packet.header.rescode,
+            authoritative: packet.header.authoritative_answer,
+            query: &req_buffer.buff[0..len],
+            response: &res_buffer.buff[0..res_buffer.pos],
+            trace: query_log::finish(),
+        },
+    );
+
+    Ok(())
+}
+
+pub fn handle_tcp_connection(server: &DnsServer, mut stream: TcpStream) -> DnsServerResult<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    let client = stream.peer_addr()?;
+
+    // Clients are allowed to send several queries over the same connection
+    loop {
+        let mut len = [0; 2];
+        // Closed by the client, or idle for too long, either way the connection is done
+        match stream.read_exact(&mut len) {
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let len = u16::from_be_bytes(len) as usize;
+
+        let mut req_buffer = BytePacketBuffer::with_size(MAX_MESSAGE_SIZE);
+        stream.read_exact(&mut req_buffer.buff[0..len])?;
+        let received = SystemTime::now();
+
+        let request = DnsPacket::from_buffer(&mut req_buffer)?;
+        let question = begin_log(server, &request);
+        let mut packet = server.handle_query(request);
+
+        let mut res_buffer = BytePacketBuffer::with_size(MAX_MESSAGE_SIZE);
+        packet.write(&mut res_buffer)?;
+
+        stream.write_all(&(res_buffer.pos as u16).to_be_bytes())?;
+        stream.write_all(&res_buffer.buff[0..res_buffer.pos])?;
+
+        log_query(
+            server,
+            LoggedQuery {
+                received,
+                answered: SystemTime::now(),
+                client,
+                transport: Transport::Tcp,
+                question: question.as_ref(),
+                rcode: packet.header.rescode,
+                authoritative: packet.header.authoritative_answer,
+                query: &req_buffer.buff[0..len],
+                response: &res_buffer.buff[0..res_buffer.pos],
+                trace: query_log::finish(),
+            },
+        );
+    }
+}
+
+// Starts tracing the query when there is a query log, the question is kept for the entry
+fn begin_log(server: &DnsServer, request: &DnsPacket) -> Option<DnsQuestion> {
+    server.query_log()?;
+    query_log::begin();
+    request.questions.first().cloned()
+}
+
+fn log_query(server: &DnsServer, query: LoggedQuery) {
+    if let Some(log) = server.query_log() {
+        log.log(&query);
+    }
+}
+
+// If the answer doesn't fit into the size the client can accept only the question (and OPT)
+// is sent back with TC set, so the client knows it has to retry over tcp
+fn write_truncated(
+    packet: &mut DnsPacket,
+    buffer: &mut BytePacketBuffer,
+) -> BytePacketBufferResult<()> {
+    if packet.write(buffer).is_ok() {
+        return Ok(());
+    }
+
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet
+        .resources
+        .retain(|record| matches!(record, DnsRecord::OPT { .. }));
+    packet.header.truncated_message = true;
+
+    *buffer = BytePacketBuffer::with_size(buffer.buff.len());
+    packet.write(buffer)
+}
@@ -1,10 +1,15 @@
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use crate::{
-    byte_packet_buffer::{BytePacketBuffer, BytePacketBufferResult},
+    byte_packet_buffer::{
+        BytePacketBuffer, BytePacketBufferResult, BUFFER_SIZE, MAX_UDP_PAYLOAD_SIZE,
+    },
+    config::IpPreference,
     dns_header::DnsHeader,
     dns_question::DnsQuestion,
     dns_records::DnsRecord,
+    name::Name,
+    query_type::QueryType,
 };
 
 #[derive(Debug, Clone, Default)]
@@ -67,41 +72,200 @@ impl DnsPacket {
         Ok(())
     }
 
-    pub fn get_first_a_record(&self) -> Option<Ipv4Addr> {
+    // A and AAAA records of the answer
+    pub fn get_addresses(&self) -> Vec<IpAddr> {
         self.answers
             .iter()
             .filter_map(|record| match record {
-                DnsRecord::A { addr, .. } => Some(*addr),
+                DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+                DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
                 _ => None,
             })
-            .next()
+            .collect()
     }
 
-    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+    pub fn get_edns(&self) -> Option<&DnsRecord> {
+        self.resources
+            .iter()
+            .find(|record| matches!(record, DnsRecord::OPT { .. }))
+    }
+
+    // How big an udp answer to this packet may be, without EDNS(0) the classic 512 bytes limit applies
+    pub fn max_udp_payload_size(&self) -> usize {
+        match self.get_edns() {
+            Some(DnsRecord::OPT { packet_len, .. }) => {
+                (*packet_len as usize).clamp(BUFFER_SIZE, MAX_UDP_PAYLOAD_SIZE)
+            }
+            _ => BUFFER_SIZE,
+        }
+    }
+
+    // Walks the CNAMEs of the answer section starting at qname. Returns the name the chain ends
+    // at and whether the answer has records of the asked type for it
+    pub fn follow_cnames(&self, qname: &Name, query_type: QueryType) -> (Name, bool) {
+        let mut name = qname.clone();
+
+        // Every CNAME is used at most once, a loop can't keep us here
+        for _ in 0..=self.answers.len() {
+            let has_records = self
+                .answers
+                .iter()
+                .any(|rec| rec.query_type() == query_type && *rec.domain() == name);
+            if has_records || query_type == QueryType::CNAME {
+                return (name, has_records);
+            }
+
+            let target = self.answers.iter().find_map(|rec| match rec {
+                DnsRecord::CNAME { domain, host, .. } if *domain == name => Some(host),
+                _ => None,
+            });
+            match target {
+                Some(target) => name = target.clone(),
+                None => break,
+            }
+        }
+
+        (name, false)
+    }
+
+    // RRSIG, NSEC and NSEC3 records go only to clients which set DO, or asked for them by type
+    pub fn strip_dnssec(&mut self, query_type: QueryType) {
+        let keep = |rec: &DnsRecord| {
+            let rec_type = rec.query_type();
+            rec_type == query_type
+                || !matches!(
+                    rec_type,
+                    QueryType::RRSIG | QueryType::NSEC | QueryType::NSEC3
+                )
+        };
+
+        self.answers.retain(keep);
+        self.authorities.retain(keep);
+        self.resources.retain(keep);
+    }
+
+    pub fn get_soa(&self) -> Option<&DnsRecord> {
+        self.authorities
+            .iter()
+            .find(|record| matches!(record, DnsRecord::SOA { .. }))
+    }
+
+    // Strips what the server, authoritative for `zone`, has no say about (the bailiwick rule):
+    // records outside of its zone, answers which aren't for the qname or the aliases it leads to,
+    // and authority records that don't cover those names
+    pub fn sanitize(&mut self, qname: &Name, zone: &Name) {
+        let in_zone = |rec: &DnsRecord| rec.domain().is_subdomain_of(zone);
+
+        let dropped = self.answers.len() + self.authorities.len() + self.resources.len();
+
+        self.answers.retain(in_zone);
+
+        let mut names = vec![qname.clone()];
+        while let Some(target) = self.answers.iter().find_map(|rec| match rec {
+            DnsRecord::CNAME { domain, host, .. }
+                if domain == names.last().unwrap() && !names.contains(host) =>
+            {
+                Some(host.clone())
+            }
+            _ => None,
+        }) {
+            names.push(target);
+        }
+        self.answers.retain(|rec| names.contains(rec.domain()));
+
+        self.authorities.retain(|rec| {
+            in_zone(rec)
+                && match rec {
+                    DnsRecord::NS { domain, .. } | DnsRecord::SOA { domain, .. } => {
+                        names.iter().any(|name| name.is_subdomain_of(domain))
+                    }
+                    _ => true,
+                }
+        });
+
+        // Addresses only of the hosts named above, glue included. Those outside of the zone have
+        // to be looked up separately
+        let hosts: Vec<Name> = self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .filter_map(|rec| match rec {
+                DnsRecord::NS { host, .. }
+                | DnsRecord::MX { host, .. }
+                | DnsRecord::SRV { host, .. } => Some(host.clone()),
+                _ => None,
+            })
+            .collect();
+        self.resources.retain(|rec| match rec {
+            DnsRecord::OPT { .. } => true,
+            DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => {
+                in_zone(rec) && hosts.contains(domain)
+            }
+            _ => in_zone(rec),
+        });
+
+        let dropped =
+            dropped - (self.answers.len() + self.authorities.len() + self.resources.len());
+        if dropped > 0 {
+            log::debug!(
+                "dropped {} out of bailiwick records from the answer for {}",
+                dropped,
+                qname
+            );
+        }
+    }
+
+    // The closest zone to the qname which the answer delegates to
+    pub fn get_zone_cut(&self, qname: &Name) -> Option<&Name> {
         self.authorities
             .iter()
             .filter_map(|record| match record {
-                DnsRecord::NS { domain, host, .. } => Some((domain.as_str(), host.as_str())),
+                DnsRecord::NS { domain, .. } if qname.is_subdomain_of(domain) => Some(domain),
                 _ => None,
             })
-            .filter(move |(domain, _)| qname.ends_with(*domain))
+            .max_by_key(|domain| domain.label_count())
     }
 
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
-        self.get_ns(qname)
-            .flat_map(|(_, host)| {
-                self.resources
-                    .iter()
-                    .filter_map(move |record| match record {
-                        DnsRecord::A { domain, addr, .. } if domain == host => Some(addr),
-                        _ => None,
-                    })
+    // Referrals carry the NS records in the authority section, the answer to a priming query
+    // in the answer section
+    fn get_ns<'a>(&'a self, zone: &'a Name) -> impl Iterator<Item = &'a Name> {
+        self.answers
+            .iter()
+            .chain(&self.authorities)
+            .filter_map(move |record| match record {
+                DnsRecord::NS { domain, host, .. } if domain == zone => Some(host),
+                _ => None,
+            })
+    }
+
+    // Addresses of the host we may use
+    fn get_glue<'a>(
+        &'a self,
+        host: &'a Name,
+        ip_preference: IpPreference,
+    ) -> impl Iterator<Item = IpAddr> + 'a {
+        self.resources
+            .iter()
+            .filter_map(move |record| match record {
+                DnsRecord::A { domain, addr, .. } if domain == host => Some(IpAddr::V4(*addr)),
+                DnsRecord::AAAA { domain, addr, .. } if domain == host => Some(IpAddr::V6(*addr)),
+                _ => None,
             })
-            .map(|addr| *addr)
-            .next()
+            .filter(move |addr| ip_preference.allows(addr))
+    }
+
+    // Addresses of the name servers of the zone we were referred to, which came with glue
+    pub fn get_resolved_ns(&self, zone: &Name, ip_preference: IpPreference) -> Vec<IpAddr> {
+        self.get_ns(zone)
+            .flat_map(|host| self.get_glue(host, ip_preference))
+            .collect()
     }
 
-    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
-        self.get_ns(qname).map(|(_, host)| host).next()
+    // Name servers of the zone we were referred to, whose address has to be looked up separately
+    pub fn get_unresolved_ns(&self, zone: &Name, ip_preference: IpPreference) -> Vec<Name> {
+        self.get_ns(zone)
+            .filter(|host| self.get_glue(host, ip_preference).next().is_none())
+            .cloned()
+            .collect()
     }
 }
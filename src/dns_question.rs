@@ -1,30 +1,37 @@
 use crate::{
     byte_packet_buffer::{BytePacketBuffer, BytePacketBufferResult},
+    name::Name,
+    query_class::QueryClass,
     query_type::QueryType,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsQuestion {
-    pub name: String,
+    pub name: Name,
     pub query_type: QueryType,
+    pub query_class: QueryClass,
 }
 
 impl DnsQuestion {
     pub fn from_buffer(buffer: &mut BytePacketBuffer) -> BytePacketBufferResult<Self> {
-        let mut result = Self::new(String::new(), QueryType::UNKNOWN(0));
+        let mut result = Self::new(Name::root(), QueryType::UNKNOWN(0));
         result.read(buffer)?;
 
         Ok(result)
     }
 
-    pub fn new(name: String, query_type: QueryType) -> Self {
-        Self { name, query_type }
+    pub fn new(name: Name, query_type: QueryType) -> Self {
+        Self {
+            name,
+            query_type,
+            query_class: QueryClass::IN,
+        }
     }
 
     pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> BytePacketBufferResult<()> {
-        buffer.read_qname(&mut self.name)?;
+        self.name = buffer.read_qname()?;
         self.query_type = QueryType::from(buffer.read_u16()?);
-        let _ = buffer.read_u16(); // class, for now we don't handle it
+        self.query_class = QueryClass::from(buffer.read_u16()?);
 
         Ok(())
     }
@@ -34,7 +41,7 @@ impl DnsQuestion {
 
         let typenum: u16 = self.query_type.into();
         buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?; // stands for class;
+        buffer.write_u16(self.query_class.into())?;
 
         Ok(())
     }
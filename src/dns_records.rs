@@ -1,58 +1,395 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
 use crate::{
-    byte_packet_buffer::{BytePacketBuffer, BytePacketBufferResult},
+    byte_packet_buffer::{BytePacketBuffer, BytePacketBufferResult, MAX_MESSAGE_SIZE},
+    errors::BytePacketBufferError,
+    name::{Name, ROOT},
+    query_class::QueryClass,
     query_type::QueryType,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+// SvcParam of SVCB and HTTPS records (RFC 9460), values are kept in their wire form
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DnsRecord {
+    // Type we don't model (RFC 3597), the RDATA is passed on as is
     UNKNOWN {
-        domain: String,
+        domain: Name,
+        class: QueryClass,
         query_type: u16,
-        data_len: u16,
+        data: Vec<u8>,
         ttl: u32,
     },
     A {
-        domain: String,
+        domain: Name,
+        class: QueryClass,
         addr: Ipv4Addr,
         ttl: u32,
     },
     NS {
-        domain: String,
-        host: String,
+        domain: Name,
+        class: QueryClass,
+        host: Name,
         ttl: u32,
     },
     CNAME {
-        domain: String,
-        host: String,
+        domain: Name,
+        class: QueryClass,
+        host: Name,
+        ttl: u32,
+    },
+    SOA {
+        domain: Name,
+        class: QueryClass,
+        m_name: Name,
+        r_name: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    },
+    PTR {
+        domain: Name,
+        class: QueryClass,
+        host: Name,
         ttl: u32,
     },
     MX {
-        domain: String,
+        domain: Name,
+        class: QueryClass,
         priority: u16,
-        host: String,
+        host: Name,
+        ttl: u32,
+    },
+    TXT {
+        domain: Name,
+        class: QueryClass,
+        // every character-string as is, they are not required to be utf8
+        data: Vec<Vec<u8>>,
         ttl: u32,
     },
     AAAA {
-        domain: String,
+        domain: Name,
+        class: QueryClass,
         addr: Ipv6Addr,
         ttl: u32,
     },
+    SRV {
+        domain: Name,
+        class: QueryClass,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: Name,
+        ttl: u32,
+    },
+    NAPTR {
+        domain: Name,
+        class: QueryClass,
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: Name,
+        ttl: u32,
+    },
+    SSHFP {
+        domain: Name,
+        class: QueryClass,
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Vec<u8>,
+        ttl: u32,
+    },
+    SVCB {
+        domain: Name,
+        class: QueryClass,
+        priority: u16,
+        target: Name,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    },
+    HTTPS {
+        domain: Name,
+        class: QueryClass,
+        priority: u16,
+        target: Name,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    },
+    CAA {
+        domain: Name,
+        class: QueryClass,
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+        ttl: u32,
+    },
+    DS {
+        domain: Name,
+        class: QueryClass,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    },
+    DNSKEY {
+        domain: Name,
+        class: QueryClass,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    },
+    RRSIG {
+        domain: Name,
+        class: QueryClass,
+        type_covered: QueryType,
+        algorithm: u8,
+        // Labels of the owner without the root and a leading *, fewer than the owner has means a wildcard
+        labels: u8,
+        original_ttl: u32,
+        // Seconds since the epoch, compared in serial number arithmetic (RFC 1982)
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: Name,
+        signature: Vec<u8>,
+        ttl: u32,
+    },
+    NSEC {
+        domain: Name,
+        class: QueryClass,
+        next_domain: Name,
+        types: Vec<QueryType>,
+        ttl: u32,
+    },
+    NSEC3 {
+        domain: Name,
+        class: QueryClass,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        // Raw hash, the owner has it base32hex encoded as the first label
+        next_hashed: Vec<u8>,
+        types: Vec<QueryType>,
+        ttl: u32,
+    },
+    // EDNS(0) pseudo record, lives only in the additional section and is always owned by the root
+    OPT {
+        packet_len: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<EdnsOption>,
+    },
 }
 
 impl DnsRecord {
+    pub fn domain(&self) -> &Name {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. } => domain,
+            DnsRecord::OPT { .. } => &ROOT,
+        }
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKNOWN { query_type, .. } => QueryType::from(*query_type),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::NAPTR { .. } => QueryType::NAPTR,
+            DnsRecord::SSHFP { .. } => QueryType::SSHFP,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+        }
+    }
+
+    pub fn class(&self) -> QueryClass {
+        match self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::NAPTR { class, .. }
+            | DnsRecord::SSHFP { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::CAA { class, .. }
+            | DnsRecord::DS { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::NSEC3 { class, .. } => *class,
+            // the class field of OPT carries the udp payload size
+            DnsRecord::OPT { .. } => QueryClass::UNKNOWN(0),
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. } => *ttl,
+            // the ttl field of OPT carries flags, it is never a time to live
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    pub fn set_ttl(&mut self, value: u32) {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. } => *ttl = value,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn set_domain(&mut self, value: Name) {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. } => *domain = value,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
     pub fn read(buffer: &mut BytePacketBuffer) -> BytePacketBufferResult<Self> {
-        let mut domain = String::new();
-        buffer.read_qname(&mut domain)?;
+        let domain = buffer.read_qname()?;
 
         let query_type_u16 = buffer.read_u16()?;
         let query_type: QueryType = query_type_u16.into();
-        let _ = buffer.read_u16()?;
+        let raw_class = buffer.read_u16()?;
+        let class = QueryClass::from(raw_class);
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
+        let start = buffer.pos();
+        let end = start + data_len as usize;
+
+        // Dynamic updates delete whole RRsets with an empty RDATA (RFC 2136 2.5.2), there is nothing to decode
+        if data_len == 0 && query_type != QueryType::OPT {
+            return Ok(DnsRecord::UNKNOWN {
+                domain,
+                class,
+                query_type: query_type_u16,
+                data: Vec::new(),
+                ttl,
+            });
+        }
 
-        match query_type {
+        let record: BytePacketBufferResult<Self> = match query_type {
             QueryType::A => {
                 let raw_addr = buffer.read_u32()?;
                 let addr = Ipv4Addr::new(
@@ -62,40 +399,102 @@ impl DnsRecord {
                     (raw_addr & 0xFF) as u8,
                 );
 
-                Ok(DnsRecord::A { domain, addr, ttl })
+                Ok(DnsRecord::A {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
             }
             QueryType::NS => {
-                let mut ns = String::new();
-                buffer.read_qname(&mut ns)?;
+                let ns = buffer.read_qname()?;
 
                 Ok(DnsRecord::NS {
                     domain,
+                    class,
                     host: ns,
                     ttl,
                 })
             }
             QueryType::CNAME => {
-                let mut cname = String::new();
-                buffer.read_qname(&mut cname)?;
+                let cname = buffer.read_qname()?;
 
                 Ok(DnsRecord::CNAME {
                     domain,
+                    class,
                     host: cname,
                     ttl,
                 })
             }
+            QueryType::SOA => {
+                let m_name = buffer.read_qname()?;
+                let r_name = buffer.read_qname()?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    class,
+                    m_name,
+                    r_name,
+                    serial: buffer.read_u32()?,
+                    refresh: buffer.read_u32()?,
+                    retry: buffer.read_u32()?,
+                    expire: buffer.read_u32()?,
+                    minimum: buffer.read_u32()?,
+                    ttl,
+                })
+            }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
-                let mut mx = String::new();
-                buffer.read_qname(&mut mx)?;
+                let mx = buffer.read_qname()?;
 
                 Ok(DnsRecord::MX {
                     domain,
+                    class,
                     priority,
                     host: mx,
                     ttl,
                 })
             }
+            QueryType::PTR => {
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::PTR {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                let mut data = Vec::new();
+
+                while buffer.pos() < end {
+                    data.push(buffer.read_character_string()?);
+                }
+
+                Ok(DnsRecord::TXT {
+                    domain,
+                    class,
+                    data,
+                    ttl,
+                })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::SRV {
+                    domain,
+                    class,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    ttl,
+                })
+            }
             QueryType::AAAA => {
                 let raw_addr1 = buffer.read_u32()?;
                 let raw_addr2 = buffer.read_u32()?;
@@ -103,28 +502,230 @@ impl DnsRecord {
                 let raw_addr4 = buffer.read_u32()?;
                 let addr = Ipv6Addr::new(
                     ((raw_addr1 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr1 >> 0) & 0xFFFF) as u16,
+                    (raw_addr1 & 0xFFFF) as u16,
                     ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 0) & 0xFFFF) as u16,
+                    (raw_addr2 & 0xFFFF) as u16,
                     ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 0) & 0xFFFF) as u16,
+                    (raw_addr3 & 0xFFFF) as u16,
                     ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 0) & 0xFFFF) as u16,
+                    (raw_addr4 & 0xFFFF) as u16,
                 );
 
-                Ok(DnsRecord::AAAA { domain, addr, ttl })
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
+            }
+            QueryType::NAPTR => Ok(DnsRecord::NAPTR {
+                domain,
+                class,
+                order: buffer.read_u16()?,
+                preference: buffer.read_u16()?,
+                flags: buffer.read_character_string()?,
+                services: buffer.read_character_string()?,
+                regexp: buffer.read_character_string()?,
+                replacement: buffer.read_qname()?,
+                ttl,
+            }),
+            QueryType::SSHFP => {
+                let algorithm = buffer.read()?;
+                let fingerprint_type = buffer.read()?;
+                let fingerprint = buffer.read_bytes(end.saturating_sub(buffer.pos()))?;
+
+                Ok(DnsRecord::SSHFP {
+                    domain,
+                    class,
+                    algorithm,
+                    fingerprint_type,
+                    fingerprint,
+                    ttl,
+                })
+            }
+            QueryType::SVCB | QueryType::HTTPS => {
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_qname()?;
+
+                let mut params = Vec::new();
+                while buffer.pos() < end {
+                    let key = buffer.read_u16()?;
+                    let len = buffer.read_u16()?;
+                    let value = buffer.read_bytes(len as usize)?;
+
+                    params.push(SvcParam { key, value });
+                }
+
+                if query_type == QueryType::SVCB {
+                    Ok(DnsRecord::SVCB {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    })
+                } else {
+                    Ok(DnsRecord::HTTPS {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    })
+                }
+            }
+            QueryType::CAA => {
+                let flags = buffer.read()?;
+                let tag = buffer.read_character_string()?;
+                let value = buffer.read_bytes(end.saturating_sub(buffer.pos()))?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    class,
+                    flags,
+                    tag: String::from_utf8_lossy(&tag).into_owned(),
+                    value,
+                    ttl,
+                })
+            }
+            QueryType::DS => Ok(DnsRecord::DS {
+                domain,
+                class,
+                key_tag: buffer.read_u16()?,
+                algorithm: buffer.read()?,
+                digest_type: buffer.read()?,
+                digest: buffer.read_bytes(end.saturating_sub(buffer.pos()))?,
+                ttl,
+            }),
+            QueryType::DNSKEY => Ok(DnsRecord::DNSKEY {
+                domain,
+                class,
+                flags: buffer.read_u16()?,
+                protocol: buffer.read()?,
+                algorithm: buffer.read()?,
+                public_key: buffer.read_bytes(end.saturating_sub(buffer.pos()))?,
+                ttl,
+            }),
+            QueryType::RRSIG => {
+                let type_covered = QueryType::from(buffer.read_u16()?);
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let signer_name = buffer.read_qname()?;
+                let signature = buffer.read_bytes(end.saturating_sub(buffer.pos()))?;
+
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    class,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let next_domain = buffer.read_qname()?;
+                let bitmap = buffer.read_bytes(end.saturating_sub(buffer.pos()))?;
+
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    class,
+                    next_domain,
+                    types: read_type_bitmap(&bitmap),
+                    ttl,
+                })
             }
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize);
+            QueryType::NSEC3 => {
+                let hash_algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt = buffer.read_character_string()?;
+                let next_hashed = buffer.read_character_string()?;
+                let bitmap = buffer.read_bytes(end.saturating_sub(buffer.pos()))?;
 
-                Ok(DnsRecord::UNKNOWN {
+                Ok(DnsRecord::NSEC3 {
                     domain,
-                    query_type: query_type_u16,
-                    data_len,
+                    class,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types: read_type_bitmap(&bitmap),
                     ttl,
                 })
             }
+            QueryType::OPT => {
+                let mut options = Vec::new();
+
+                while buffer.pos() < end {
+                    let code = buffer.read_u16()?;
+                    let len = buffer.read_u16()?;
+                    let data = buffer.read_bytes(len as usize)?;
+
+                    options.push(EdnsOption { code, data });
+                }
+
+                // For OPT the class is the udp payload size and the ttl is
+                // |extended rcode 8bits| |version 8bits| |DO 1bit| |Z 15bits|
+                Ok(DnsRecord::OPT {
+                    packet_len: raw_class,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    dnssec_ok: (ttl & (1 << 15)) > 0,
+                    options,
+                })
+            }
+            QueryType::UNKNOWN(_) => Ok(DnsRecord::UNKNOWN {
+                domain,
+                class,
+                query_type: query_type_u16,
+                data: buffer.read_bytes(data_len as usize)?,
+                ttl,
+            }),
+        };
+        let record = record?;
+
+        // The fields have to fill the RDATA exactly, anything else is a malformed record
+        if buffer.pos() != end {
+            return Err(BytePacketBufferError::RdataLengthMismatch {
+                expected: data_len as usize,
+                read: buffer.pos() - start,
+            });
         }
+
+        Ok(record)
+    }
+
+    // Typed record out of RDATA in wire form, e.g. given as \# in a zone file
+    pub fn from_rdata(
+        domain: &Name,
+        class: QueryClass,
+        query_type: QueryType,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> BytePacketBufferResult<Self> {
+        let mut buffer = BytePacketBuffer::with_size(MAX_MESSAGE_SIZE);
+        buffer.write_qname(domain)?;
+        buffer.write_u16(query_type.into())?;
+        buffer.write_u16(class.into())?;
+        buffer.write_u32(ttl)?;
+        buffer.write_u16(rdata_len(0, rdata)?)?;
+        buffer.write_bytes(rdata)?;
+
+        buffer.seek(0);
+        Self::read(&mut buffer)
     }
 
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> BytePacketBufferResult<usize> {
@@ -133,31 +734,43 @@ impl DnsRecord {
         match *self {
             DnsRecord::A {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::A.into())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.into())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(4)?; //data length
 
-                let octets = addr.octets();
-                for i in 0..4 {
-                    buffer.write_u8(octets[i])?;
+                for octet in &addr.octets() {
+                    buffer.write_u8(*octet)?;
                 }
             }
-            DnsRecord::UNKNOWN { .. } => {
-                println!("Skipping record {:?}", self);
+            DnsRecord::UNKNOWN {
+                ref domain,
+                class,
+                query_type,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(query_type)?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(0, data)?)?;
+                buffer.write_bytes(data)?;
             }
             DnsRecord::AAAA {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::AAAA.into())?;
-                buffer.write_u16(1)?; // CLASS
+                buffer.write_u16(class.into())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(16)?; // RDLENGTH
 
@@ -167,12 +780,13 @@ impl DnsRecord {
             }
             DnsRecord::NS {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::NS.into())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.into())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos();
@@ -185,12 +799,13 @@ impl DnsRecord {
             }
             DnsRecord::CNAME {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::CNAME.into())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.into())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos();
@@ -201,15 +816,371 @@ impl DnsRecord {
                 let size = buffer.pos() - (pos + 2);
                 buffer.set_u16(pos, size as u16);
             }
+            DnsRecord::SOA {
+                ref domain,
+                class,
+                ref m_name,
+                ref r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(m_name)?;
+                buffer.write_qname(r_name)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::TXT {
+                ref domain,
+                class,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                for text in data {
+                    buffer.write_character_string(text)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::SRV {
+                ref domain,
+                class,
+                priority,
+                weight,
+                port,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                buffer.write_qname_uncompressed(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::PTR {
+                ref domain,
+                class,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::NAPTR {
+                ref domain,
+                class,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NAPTR.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(order)?;
+                buffer.write_u16(preference)?;
+                buffer.write_character_string(flags)?;
+                buffer.write_character_string(services)?;
+                buffer.write_character_string(regexp)?;
+                buffer.write_qname_uncompressed(replacement)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::SSHFP {
+                ref domain,
+                class,
+                algorithm,
+                fingerprint_type,
+                ref fingerprint,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SSHFP.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(2, fingerprint)?)?;
+
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(fingerprint_type)?;
+                buffer.write_bytes(fingerprint)?;
+            }
+            DnsRecord::SVCB {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            }
+            | DnsRecord::HTTPS {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(self.query_type().into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_qname_uncompressed(target)?;
+                for param in params {
+                    buffer.write_u16(param.key)?;
+                    buffer.write_u16(rdata_len(0, &param.value)?)?;
+                    buffer.write_bytes(&param.value)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::CAA {
+                ref domain,
+                class,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CAA.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u8(flags)?;
+                buffer.write_character_string(tag.as_bytes())?;
+                buffer.write_bytes(value)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::DS {
+                ref domain,
+                class,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DS.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(4, digest)?)?;
+
+                buffer.write_u16(key_tag)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(digest_type)?;
+                buffer.write_bytes(digest)?;
+            }
+            DnsRecord::DNSKEY {
+                ref domain,
+                class,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(rdata_len(4, public_key)?)?;
+
+                buffer.write_u16(flags)?;
+                buffer.write_u8(protocol)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_bytes(public_key)?;
+            }
+            DnsRecord::RRSIG {
+                ref domain,
+                class,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer_name,
+                ref signature,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(type_covered.into())?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(labels)?;
+                buffer.write_u32(original_ttl)?;
+                buffer.write_u32(expiration)?;
+                buffer.write_u32(inception)?;
+                buffer.write_u16(key_tag)?;
+                // Validators hash the RDATA as it is on the wire, a pointer there would break the signature
+                buffer.write_qname_uncompressed(signer_name)?;
+                buffer.write_bytes(signature)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::NSEC {
+                ref domain,
+                class,
+                ref next_domain,
+                ref types,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname_uncompressed(next_domain)?;
+                buffer.write_bytes(&write_type_bitmap(types))?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::NSEC3 {
+                ref domain,
+                class,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ref next_hashed,
+                ref types,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC3.into())?;
+                buffer.write_u16(class.into())?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u8(hash_algorithm)?;
+                buffer.write_u8(flags)?;
+                buffer.write_u16(iterations)?;
+                buffer.write_character_string(salt)?;
+                buffer.write_character_string(next_hashed)?;
+                buffer.write_bytes(&write_type_bitmap(types))?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
+            DnsRecord::OPT {
+                packet_len,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                ref options,
+            } => {
+                buffer.write_qname(&Name::root())?;
+                buffer.write_u16(QueryType::OPT.into())?;
+                buffer.write_u16(packet_len)?;
+                buffer.write_u32(
+                    ((extended_rcode as u32) << 24)
+                        | ((version as u32) << 16)
+                        | ((dnssec_ok as u32) << 15),
+                )?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                for option in options {
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(rdata_len(0, &option.data)?)?;
+                    buffer.write_bytes(&option.data)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16);
+            }
             DnsRecord::MX {
                 ref domain,
+                class,
                 priority,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::MX.into())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.into())?;
                 buffer.write_u32(ttl)?;
 
                 let pos = buffer.pos();
@@ -226,3 +1197,498 @@ impl DnsRecord {
         Ok(buffer.pos() - start_pos)
     }
 }
+
+// Length of the fixed fields plus the data, as the 16 bits the wire has for it
+fn rdata_len(fixed: usize, data: &[u8]) -> BytePacketBufferResult<u16> {
+    let length = fixed + data.len();
+    u16::try_from(length).map_err(|_| BytePacketBufferError::RdataTooLong { length })
+}
+
+// <character-string> in quotes, anything unprintable as \DDD
+fn fmt_character_string(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+            0x20..=0x7e => write!(f, "{}", *byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn fmt_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+}
+
+fn fmt_types(f: &mut fmt::Formatter, types: &[QueryType]) -> fmt::Result {
+    types
+        .iter()
+        .try_for_each(|query_type| write!(f, " {}", query_type))
+}
+
+// YYYYMMDDHHmmSS in UTC, the form RRSIG times are usually written in
+fn fmt_timestamp(f: &mut fmt::Formatter, timestamp: u32) -> fmt::Result {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    write!(
+        f,
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date, Howard Hinnant's algorithm
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+// The inverse of civil_from_days
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+const BASE32HEX_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+// RFC 4648 base32 with the extended hex alphabet and no padding, NSEC3 hashes are written in it
+pub fn base32hex_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block
+            .iter()
+            .fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for idx in 0..chars {
+            let value = (bits >> (35 - idx * 5)) & 0x1F;
+            text.push(BASE32HEX_ALPHABET[value as usize] as char);
+        }
+    }
+
+    text
+}
+
+pub fn base32hex_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+
+    for c in text {
+        let value = BASE32HEX_ALPHABET
+            .iter()
+            .position(|symbol| symbol.eq_ignore_ascii_case(c))?;
+
+        bits = (bits << 5) | value as u32;
+        bit_count += 5;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+// NSEC and NSEC3 type bitmaps (RFC 4034 section 4.1.2), windows of 256 types each with
+// one bit per type
+fn read_type_bitmap(bitmap: &[u8]) -> Vec<QueryType> {
+    let mut types = Vec::new();
+    let mut rest = bitmap;
+
+    while let [window, len, tail @ ..] = rest {
+        let (bits, tail) = tail.split_at((*len as usize).min(tail.len()));
+
+        for (idx, byte) in bits.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let value = ((*window as u16) << 8) | (idx * 8 + bit) as u16;
+                    types.push(QueryType::from(value));
+                }
+            }
+        }
+
+        rest = tail;
+    }
+
+    types
+}
+
+fn write_type_bitmap(types: &[QueryType]) -> Vec<u8> {
+    let mut values: Vec<u16> = types
+        .iter()
+        .map(|query_type| (*query_type).into())
+        .collect();
+    values.sort_unstable();
+    values.dedup();
+
+    let mut bitmap = Vec::new();
+    for window in 0..=255u8 {
+        let bits: Vec<u8> = values
+            .iter()
+            .filter(|value| (**value >> 8) as u8 == window)
+            .map(|value| (*value & 0xFF) as u8)
+            .collect();
+        let Some(last) = bits.last() else {
+            continue;
+        };
+
+        let mut block = vec![0u8; *last as usize / 8 + 1];
+        for bit in bits {
+            block[bit as usize / 8] |= 0x80 >> (bit % 8);
+        }
+
+        bitmap.push(window);
+        bitmap.push(block.len() as u8);
+        bitmap.extend(block);
+    }
+
+    bitmap
+}
+
+// RFC 9460 presentation of the well known keys, the rest as keyNNNNN="..."
+fn fmt_svc_params(f: &mut fmt::Formatter, params: &[SvcParam]) -> fmt::Result {
+    for param in params {
+        let value = &param.value;
+
+        match param.key {
+            1 => {
+                write!(f, " alpn=")?;
+                let mut rest = value.as_slice();
+                let mut delim = "";
+                while let Some((&len, tail)) = rest.split_first() {
+                    let (id, tail) = tail.split_at((len as usize).min(tail.len()));
+                    write!(f, "{}{}", delim, String::from_utf8_lossy(id))?;
+                    delim = ",";
+                    rest = tail;
+                }
+            }
+            2 if value.is_empty() => write!(f, " no-default-alpn")?,
+            3 if value.len() == 2 => {
+                write!(f, " port={}", u16::from_be_bytes([value[0], value[1]]))?
+            }
+            4 if value.len() % 4 == 0 => {
+                let hints: Vec<String> = value
+                    .chunks(4)
+                    .map(|octets| {
+                        Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).to_string()
+                    })
+                    .collect();
+                write!(f, " ipv4hint={}", hints.join(","))?;
+            }
+            6 if value.len() % 16 == 0 => {
+                let hints: Vec<String> = value
+                    .chunks(16)
+                    .map(|octets| Ipv6Addr::from(<[u8; 16]>::try_from(octets).unwrap()).to_string())
+                    .collect();
+                write!(f, " ipv6hint={}", hints.join(","))?;
+            }
+            key => {
+                write!(f, " key{}=", key)?;
+                fmt_character_string(f, value)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Zone file presentation, types we don't know in the RFC 3597 form
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let DnsRecord::OPT {
+            packet_len,
+            extended_rcode,
+            version,
+            dnssec_ok,
+            options,
+        } = self
+        {
+            return write!(
+                f,
+                "; EDNS version {}, udp {}, extended rcode {}{}, {} options",
+                version,
+                packet_len,
+                extended_rcode,
+                if *dnssec_ok { ", do" } else { "" },
+                options.len()
+            );
+        }
+
+        write!(f, "{}", self.domain())?;
+        write!(f, " {} ", self.ttl())?;
+        write!(f, "{} {} ", self.class(), self.query_type())?;
+
+        match self {
+            DnsRecord::UNKNOWN { data, .. } => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    fmt_hex(f, data)?;
+                }
+                Ok(())
+            }
+            DnsRecord::A { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::AAAA { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::NS { host, .. }
+            | DnsRecord::CNAME { host, .. }
+            | DnsRecord::PTR { host, .. } => write!(f, "{}", host),
+            DnsRecord::SOA {
+                m_name,
+                r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} {} {} {}",
+                    m_name, r_name, serial, refresh, retry, expire, minimum
+                )
+            }
+            DnsRecord::MX { priority, host, .. } => {
+                write!(f, "{} {}", priority, host)
+            }
+            DnsRecord::TXT { data, .. } => {
+                let mut delim = "";
+                for text in data {
+                    write!(f, "{}", delim)?;
+                    fmt_character_string(f, text)?;
+                    delim = " ";
+                }
+                Ok(())
+            }
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                host,
+                ..
+            } => {
+                write!(f, "{} {} {} {}", priority, weight, port, host)
+            }
+            DnsRecord::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => {
+                write!(f, "{} {} ", order, preference)?;
+                for field in [flags, services, regexp] {
+                    fmt_character_string(f, field)?;
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", replacement)
+            }
+            DnsRecord::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+                ..
+            } => {
+                write!(f, "{} {} ", algorithm, fingerprint_type)?;
+                fmt_hex(f, fingerprint)
+            }
+            DnsRecord::SVCB {
+                priority,
+                target,
+                params,
+                ..
+            }
+            | DnsRecord::HTTPS {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                write!(f, "{} {}", priority, target)?;
+                fmt_svc_params(f, params)
+            }
+            DnsRecord::CAA {
+                flags, tag, value, ..
+            } => {
+                write!(f, "{} {} ", flags, tag)?;
+                fmt_character_string(f, value)
+            }
+            DnsRecord::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ..
+            } => {
+                write!(f, "{} {} {} ", key_tag, algorithm, digest_type)?;
+                fmt_hex(f, digest)
+            }
+            DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {}",
+                    flags,
+                    protocol,
+                    algorithm,
+                    BASE64.encode(public_key)
+                )
+            }
+            DnsRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} ",
+                    type_covered, algorithm, labels, original_ttl
+                )?;
+                fmt_timestamp(f, *expiration)?;
+                write!(f, " ")?;
+                fmt_timestamp(f, *inception)?;
+                write!(
+                    f,
+                    " {} {} {}",
+                    key_tag,
+                    signer_name,
+                    BASE64.encode(signature)
+                )
+            }
+            DnsRecord::NSEC {
+                next_domain, types, ..
+            } => {
+                write!(f, "{}", next_domain)?;
+                fmt_types(f, types)
+            }
+            DnsRecord::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+                ..
+            } => {
+                write!(f, "{} {} {} ", hash_algorithm, flags, iterations)?;
+                if salt.is_empty() {
+                    write!(f, "-")?;
+                } else {
+                    fmt_hex(f, salt)?;
+                }
+                write!(f, " {}", base32hex_encode(next_hashed))?;
+                fmt_types(f, types)
+            }
+            DnsRecord::OPT { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ds(digest: Vec<u8>) -> DnsRecord {
+        DnsRecord::DS {
+            domain: "example.".parse().unwrap(),
+            class: QueryClass::IN,
+            key_tag: 12345,
+            algorithm: 13,
+            digest_type: 2,
+            digest,
+            ttl: 3600,
+        }
+    }
+
+    // An A record whose RDLENGTH says data_len, followed by the start of another record
+    fn a_record(data_len: u16) -> BytePacketBuffer {
+        let mut buffer = BytePacketBuffer::new();
+        buffer
+            .write_qname(&"www.example.".parse().unwrap())
+            .unwrap();
+        buffer.write_u16(QueryType::A.into()).unwrap();
+        buffer.write_u16(QueryClass::IN.into()).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(data_len).unwrap();
+        buffer.write_bytes(&[192, 0, 2, 1, 0, 0]).unwrap();
+        buffer.seek(0);
+        buffer
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let record = ds(vec![0xAB; 32]);
+        let mut buffer = BytePacketBuffer::new();
+        record.write(&mut buffer).unwrap();
+        buffer.seek(0);
+
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+    }
+
+    #[test]
+    fn rdata_has_to_be_exactly_as_long_as_its_fields() {
+        assert!(DnsRecord::read(&mut a_record(4)).is_ok());
+        for data_len in [2, 6] {
+            assert!(matches!(
+                DnsRecord::read(&mut a_record(data_len)),
+                Err(BytePacketBufferError::RdataLengthMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn oversized_rdata_is_not_written() {
+        let mut buffer = BytePacketBuffer::with_size(2 * MAX_MESSAGE_SIZE);
+        assert!(matches!(
+            ds(vec![0; MAX_MESSAGE_SIZE]).write(&mut buffer),
+            Err(BytePacketBufferError::RdataTooLong { .. })
+        ));
+    }
+}
@@ -1,93 +1,1030 @@
-use rand::{rngs::ThreadRng, Rng};
-use std::net::{Ipv4Addr, UdpSocket};
+use rand::Rng;
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{Mutex, MutexGuard, TryLockError},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    byte_packet_buffer::BytePacketBuffer,
+    blocklist::Blocklist,
+    byte_packet_buffer::{BytePacketBuffer, MAX_MESSAGE_SIZE, MAX_UDP_PAYLOAD_SIZE},
+    chaos::ServerIdentity,
+    config::{CacheConfig, Config, IpPreference},
+    dns_cache::DnsCache,
+    dns_header::DnsHeader,
     dns_packet::DnsPacket,
     dns_question::DnsQuestion,
+    dns_records::DnsRecord,
+    dnssec::{self, RrSet, Security, TrustAnchors},
     errors::{DnsServerError, DnsServerResult},
+    forwarder::{Forwarder, UpstreamGroup},
+    in_flight::InFlightLimiter,
+    metrics::{Gauges, Metrics},
+    name::Name,
+    query_class::QueryClass,
+    query_log::{self, QueryLog},
     query_type::QueryType,
     result_code::ResultCode,
+    server_stats::ServerStats,
+    zone::Zones,
 };
 
+// Where name servers listen
+const NAME_SERVER_PORT: u16 = 53;
+// Source ports for upstream queries are picked at random from this range
+const SOURCE_PORTS: std::ops::RangeInclusive<u16> = 1024..=65535;
+const SOURCE_PORT_ATTEMPTS: usize = 16;
+// Aliases followed for a single query, across all the zones they lead through
+const MAX_CNAME_CHAIN: usize = 8;
+// A failed priming isn't repeated sooner, the root hints are used meanwhile
+const PRIMING_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+// Lookups a single client query may set off. Glueless name servers and the DNSSEC chain of trust
+// start lookups of their own, a delegation cycle would recurse until the stack overflows
+const MAX_LOOKUPS: usize = 64;
+
+// Every upstream query goes out from its own random port, so a spoofer has to guess it along with the id.
+// The socket is of the same family as the server it talks to
+fn bind_random_port(server: &SocketAddr) -> io::Result<UdpSocket> {
+    let mut rng = rand::thread_rng();
+    let unspecified = match server {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    for _ in 0..SOURCE_PORT_ATTEMPTS {
+        match UdpSocket::bind((unspecified, rng.gen_range(SOURCE_PORTS))) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Lots of ports taken, let the os pick a free one
+    UdpSocket::bind((unspecified, 0))
+}
+
+// What is left of MAX_LOOKUPS for the query being answered, shared by all its nested lookups
+struct LookupBudget {
+    remaining: Cell<usize>,
+}
+
+impl LookupBudget {
+    fn new() -> Self {
+        Self {
+            remaining: Cell::new(MAX_LOOKUPS),
+        }
+    }
+
+    fn spend(&self, qname: &Name) -> DnsServerResult<()> {
+        match self.remaining.get() {
+            0 => Err(DnsServerError::TooManyLookups {
+                qname: qname.to_string(),
+                limit: MAX_LOOKUPS,
+            }),
+            remaining => {
+                self.remaining.set(remaining - 1);
+                Ok(())
+            }
+        }
+    }
+}
+
 pub struct DnsServer {
-    root: Ipv4Addr,
-    port: u16,
-    rng: ThreadRng,
+    root_hints: Vec<IpAddr>,
+    name_server_port: u16,
+    ip_preference: IpPreference,
+    recursion: bool,
+    randomize_case: bool,
+    initial_timeout: Duration,
+    // How many times a silent name server is asked, the timeout doubles every round
+    query_rounds: usize,
+    // Shared between all the workers, so the lock is held only for single cache operations
+    cache: Mutex<DnsCache>,
+    // Held while the root is primed, so the workers don't all prime it at once. Holds when the
+    // last priming failed
+    priming: Mutex<Option<Instant>>,
+    in_flight: InFlightLimiter,
+    forwarder: Forwarder,
+    server_stats: ServerStats,
+    zones: Zones,
+    identity: ServerIdentity,
+    // Set when answers of the recursion are DNSSEC validated
+    trust_anchors: Option<TrustAnchors>,
+    query_log: Option<QueryLog>,
+    blocklist: Option<Blocklist>,
+    metrics: Metrics,
+}
+
+pub struct DnsServerBuilder {
+    root_hints: Vec<IpAddr>,
+    name_server_port: u16,
+    ip_preference: IpPreference,
+    recursion: bool,
+    randomize_case: bool,
+    initial_timeout: Duration,
+    query_rounds: usize,
+    cache: CacheConfig,
+    max_in_flight: usize,
+    forwarder: Forwarder,
+    zones: Zones,
+    identity: ServerIdentity,
+    trust_anchors: Option<TrustAnchors>,
+    query_log: Option<QueryLog>,
+    blocklist: Option<Blocklist>,
+}
+
+impl DnsServerBuilder {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            root_hints: config.root_hints.clone(),
+            name_server_port: NAME_SERVER_PORT,
+            ip_preference: config.ip_preference,
+            recursion: config.recursion,
+            randomize_case: config.randomize_case,
+            initial_timeout: Duration::from_millis(config.timeouts.initial_ms),
+            query_rounds: config.timeouts.query_rounds,
+            cache: config.cache.clone(),
+            max_in_flight: config.max_in_flight,
+            forwarder: Forwarder::from_config(config.mode, &config.forward),
+            zones: Zones::default(),
+            identity: ServerIdentity::from_config(&config.identity),
+            trust_anchors: None,
+            query_log: None,
+            blocklist: None,
+        }
+    }
+
+    // Zone files are loaded by the caller, so it can decide what to do when one is broken
+    pub fn zones(mut self, zones: Zones) -> Self {
+        self.zones = zones;
+        self
+    }
+
+    // Turns the validation on, like zones the anchors are loaded by the caller
+    pub fn trust_anchors(mut self, trust_anchors: TrustAnchors) -> Self {
+        self.trust_anchors = Some(trust_anchors);
+        self
+    }
+
+    // Opened by the caller as well, a log that can't be written to stops the startup
+    pub fn query_log(mut self, query_log: QueryLog) -> Self {
+        self.query_log = Some(query_log);
+        self
+    }
+
+    // The lists are read by the caller, the server reloads them later on
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    // Tests run their name servers on a port of their own
+    #[cfg(test)]
+    pub fn name_server_port(mut self, port: u16) -> Self {
+        self.name_server_port = port;
+        self
+    }
+
+    pub fn build(self) -> DnsServer {
+        DnsServer {
+            root_hints: self.root_hints,
+            name_server_port: self.name_server_port,
+            ip_preference: self.ip_preference,
+            recursion: self.recursion,
+            randomize_case: self.randomize_case,
+            initial_timeout: self.initial_timeout,
+            query_rounds: self.query_rounds.max(1),
+            cache: Mutex::new(DnsCache::from_config(&self.cache)),
+            priming: Mutex::new(None),
+            in_flight: InFlightLimiter::new(self.max_in_flight),
+            forwarder: self.forwarder,
+            server_stats: ServerStats::default(),
+            zones: self.zones,
+            identity: self.identity,
+            trust_anchors: self.trust_anchors,
+            query_log: self.query_log,
+            blocklist: self.blocklist,
+            metrics: Metrics::default(),
+        }
+    }
 }
 
 impl DnsServer {
-    pub fn recursive_lookup(
-        &mut self,
-        qname: &str,
+    pub fn builder(config: &Config) -> DnsServerBuilder {
+        DnsServerBuilder::from_config(config)
+    }
+
+    pub fn query_log(&self) -> Option<&QueryLog> {
+        self.query_log.as_ref()
+    }
+
+    // Logs the hits of the blocklists and reloads them when their files change, never returns
+    // while there are any
+    pub fn watch_blocklist(&self) {
+        if let Some(blocklist) = &self.blocklist {
+            blocklist.watch();
+        }
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let gauges = {
+            let cache = self.cache();
+            Gauges {
+                cache_entries: cache.len(),
+                negative_cache_entries: cache.negative_len(),
+                in_flight: self.in_flight.current(),
+            }
+        };
+
+        self.metrics.render(&gauges)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, DnsCache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn cached_lookup(&self, qname: &Name, query_type: QueryType) -> Option<DnsPacket> {
+        let mut entry = self.cache().lookup_entry(qname, query_type);
+        // An alias answers every type, the caller follows it to the target
+        if entry.is_none() && query_type != QueryType::CNAME {
+            entry = self.cache().lookup_entry(qname, QueryType::CNAME);
+        }
+
+        if let Some((records, secure)) = entry {
+            log::debug!("cache hit for {} {}", query_type, qname);
+
+            let mut packet = DnsPacket::default();
+            packet.header.rescode = ResultCode::NOERROR;
+            packet.header.authed_data = secure;
+            packet.answers = records;
+
+            return Some(packet);
+        }
+
+        if let Some((rescode, authorities, secure)) =
+            self.cache().lookup_negative(qname, query_type)
+        {
+            log::debug!("negative cache hit for {} {}", query_type, qname);
+
+            let mut packet = DnsPacket::default();
+            packet.header.rescode = rescode;
+            packet.header.authed_data = secure;
+            packet.authorities = authorities;
+
+            return Some(packet);
+        }
+
+        None
+    }
+
+    // With checking disabled (CD) bogus answers are returned as they are instead of an error
+    fn recursive_lookup(
+        &self,
+        qname: &Name,
         query_type: QueryType,
+        checking_disabled: bool,
+        budget: &LookupBudget,
     ) -> DnsServerResult<DnsPacket> {
-        let mut name_server = self.root.clone();
+        if let Some(packet) = self.cached_lookup(qname, query_type) {
+            return Ok(packet);
+        }
+        budget.spend(qname)?;
+
+        // The DS of a zone lives in its parent, the servers of the zone itself don't have it
+        let start = match query_type {
+            QueryType::DS => qname.parent().unwrap_or_else(Name::root),
+            _ => qname.clone(),
+        };
+
+        // Start from the closest delegation we already know instead of walking from the root every time.
+        // The zone is what the servers we ask are authoritative for, anything outside of it is dropped
+        let closest = self.cache().closest_name_servers(&start);
+        // Priming takes the cache lock itself, so the guard above must be gone by then
+        let (mut zone, mut name_servers) = closest
+            .filter(|(_, addrs)| addrs.iter().any(|addr| self.ip_preference.allows(addr)))
+            .unwrap_or_else(|| (Name::root(), self.prime()));
+        // NS hosts we were referred to without glue, they are resolved only when needed
+        let mut unresolved_ns: Vec<Name> = Vec::new();
 
         loop {
-            let ns_copy = name_server;
-            println!(
-                "attempting lookup of {:?} {} with ns {}",
-                query_type, qname, name_server
+            log::debug!(
+                "attempting lookup of {} {} with ns {:?}",
+                query_type,
+                qname,
+                name_servers
             );
 
-            let response = self.lookup((ns_copy, 53), qname, query_type)?;
+            let mut response = match self.query_name_servers(
+                &self.name_server_addrs(&name_servers),
+                qname,
+                query_type,
+            ) {
+                Ok(response) => response,
+                Err(e) => match self.resolve_next_ns(&mut unresolved_ns, budget)? {
+                    Some(addrs) => {
+                        name_servers = addrs;
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+            };
 
-            println!("response - {:?}", response);
+            log::trace!("response - {:?}", response);
 
-            if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
-                return Ok(response);
-            }
-            if response.header.rescode == ResultCode::NXDOMAIN {
-                return Ok(response);
+            response.sanitize(qname, &zone);
+
+            let answered = (!response.answers.is_empty()
+                && response.header.rescode == ResultCode::NOERROR)
+                || response.header.rescode == ResultCode::NXDOMAIN;
+
+            // A referral has to lead down towards the qname, sideways or up it would never end
+            match response.get_zone_cut(qname) {
+                Some(cut)
+                    if !answered
+                        && *cut != zone
+                        && !(query_type == QueryType::DS && cut == qname) =>
+                {
+                    zone = cut.clone()
+                }
+                _ => {
+                    return self.complete_lookup(
+                        response,
+                        qname,
+                        query_type,
+                        &zone,
+                        checking_disabled,
+                        budget,
+                    )
+                }
             }
+            self.cache_response(qname, query_type, &response);
+
+            let resolved_ns = response.get_resolved_ns(&zone, self.ip_preference);
+            unresolved_ns = response.get_unresolved_ns(&zone, self.ip_preference);
 
-            if let Some(new_ns) = response.get_resolved_ns(qname) {
-                name_server = new_ns;
+            if !resolved_ns.is_empty() {
+                name_servers = resolved_ns;
 
                 continue;
             }
 
-            let new_ns_name = match response.get_unresolved_ns(qname) {
-                Some(x) => x,
+            match self.resolve_next_ns(&mut unresolved_ns, budget)? {
+                Some(addrs) => name_servers = addrs,
                 None => return Ok(response),
+            }
+        }
+    }
+
+    // The final answer of a recursion, validated when DNSSEC is on. Bogus answers aren't cached
+    fn complete_lookup(
+        &self,
+        mut response: DnsPacket,
+        qname: &Name,
+        query_type: QueryType,
+        zone: &Name,
+        checking_disabled: bool,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<DnsPacket> {
+        if let Some(trust_anchors) = &self.trust_anchors {
+            match self.validate(trust_anchors, &response, qname, query_type, zone, budget) {
+                Ok(secure) => response.header.authed_data = secure,
+                // The client validates for itself, it gets the data as it came
+                Err(e @ DnsServerError::Bogus { .. }) if checking_disabled => {
+                    log::warn!("{}, passed on as the client disabled checking", e);
+                    return Ok(response);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.cache_response(qname, query_type, &response);
+
+        Ok(response)
+    }
+
+    // Whether the answer is secure, or provably insecure. Anything else is bogus
+    fn validate(
+        &self,
+        trust_anchors: &TrustAnchors,
+        response: &DnsPacket,
+        qname: &Name,
+        query_type: QueryType,
+        zone: &Name,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<bool> {
+        let bogus = |reason: String| DnsServerError::Bogus {
+            qname: qname.to_string(),
+            reason,
+        };
+        let now = dnssec::now();
+
+        let self_signed =
+            response.answers.iter().chain(&response.authorities).any(
+                |rec| matches!(rec, DnsRecord::RRSIG { signer_name, .. } if signer_name == qname),
+            );
+        // The keys of a zone are vouched for by the DS in its parent, not by the keys themselves
+        if query_type == QueryType::DNSKEY && (self_signed || zone == qname) {
+            return self.validate_keys(trust_anchors, response, qname, now, budget);
+        }
+        // The DS is signed by the parent, the zone below the cut has no say about it
+        if query_type == QueryType::DS && self_signed {
+            return Err(bogus("the DS is signed by the zone itself".to_string()));
+        }
+
+        let mut secure = true;
+        let mut wildcards = Vec::new();
+        for rrset in dnssec::rrsets(&response.answers) {
+            match self.verify_rrset(&rrset, zone, now, budget)? {
+                Some(rrsig) => wildcards.extend(
+                    dnssec::wildcard_source(rrset.owner, rrsig)
+                        .map(|source| (rrset.owner.clone(), source)),
+                ),
+                None => secure = false,
+            }
+        }
+
+        // A chain which leads out of the answer isn't a denial, its end is looked up on its own
+        let (end, complete) = response.follow_cnames(qname, query_type);
+        let denial =
+            !complete && (end == *qname || response.header.rescode == ResultCode::NXDOMAIN);
+        if !secure || (!denial && wildcards.is_empty()) {
+            return Ok(secure);
+        }
+
+        // NXDOMAIN, NODATA and answers from a wildcard are proven by the NSEC records of the
+        // authority section
+        let proof: Vec<RrSet> = dnssec::rrsets(&response.authorities)
+            .into_iter()
+            .filter(|rrset| {
+                matches!(
+                    rrset.query_type,
+                    QueryType::SOA | QueryType::NSEC | QueryType::NSEC3
+                )
+            })
+            .collect();
+        if proof.is_empty() {
+            return match self.zone_keys(zone, budget)? {
+                Some(_) => Err(bogus("the answer has no NSEC records".to_string())),
+                None => Ok(false),
             };
+        }
+        for rrset in &proof {
+            if self.verify_rrset(rrset, zone, now, budget)?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        let mut proofs: Vec<Security> = wildcards
+            .iter()
+            .map(|(owner, source)| {
+                dnssec::prove_wildcard_answer(&response.authorities, owner, source)
+            })
+            .collect();
+        if denial {
+            proofs.push(dnssec::prove_denial(
+                &response.authorities,
+                &end,
+                query_type,
+                response.header.rescode == ResultCode::NXDOMAIN,
+            ));
+        }
+
+        for security in proofs {
+            match security {
+                Security::Secure => {}
+                Security::Insecure => secure = false,
+                Security::Bogus(reason) => return Err(bogus(reason)),
+            }
+        }
+
+        Ok(secure)
+    }
+
+    // DNSKEYs are trusted when one of them matches a DS of the parent, or a trust anchor, and
+    // signs the whole set
+    fn validate_keys(
+        &self,
+        trust_anchors: &TrustAnchors,
+        response: &DnsPacket,
+        zone: &Name,
+        now: u32,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<bool> {
+        let bogus = |reason: String| DnsServerError::Bogus {
+            qname: zone.to_string(),
+            reason,
+        };
+
+        let Some(ds) = self.delegation_ds(trust_anchors, zone, budget)? else {
+            return Ok(false);
+        };
+        // Algorithms we can't verify leave the zone as good as unsigned (RFC 4035 section 5.2)
+        if !ds.iter().any(dnssec::is_supported_ds) {
+            log::debug!("{} is signed with algorithms we don't support", zone);
+            return Ok(false);
+        }
+
+        let rrsets = dnssec::rrsets(&response.answers);
+        let Some(keys) = rrsets
+            .iter()
+            .find(|rrset| rrset.owner == zone && rrset.query_type == QueryType::DNSKEY)
+        else {
+            return Err(bogus("the zone has a DS but no DNSKEY".to_string()));
+        };
+
+        let trusted: Vec<DnsRecord> = keys
+            .records
+            .iter()
+            .filter(|key| ds.iter().any(|ds| dnssec::ds_matches(ds, key)))
+            .map(|key| (*key).clone())
+            .collect();
+        if trusted.is_empty() {
+            return Err(bogus("none of the DNSKEYs matches the DS".to_string()));
+        }
+
+        dnssec::verify_rrset(keys, &trusted, now).map_err(bogus)?;
+
+        Ok(true)
+    }
+
+    // The DS set of the zone from a trust anchor or the parent. None when the parent proves the
+    // zone unsigned, or no trust anchor covers it at all
+    fn delegation_ds(
+        &self,
+        trust_anchors: &TrustAnchors,
+        zone: &Name,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<Option<Vec<DnsRecord>>> {
+        let anchored = trust_anchors.ds_set(zone);
+        if !anchored.is_empty() {
+            return Ok(Some(anchored));
+        }
+        if !trust_anchors.covers(zone) {
+            return Ok(None);
+        }
+
+        let response = self.recursive_lookup(zone, QueryType::DS, false, budget)?;
+        if !response.header.authed_data {
+            return Ok(None);
+        }
+
+        let ds: Vec<DnsRecord> = response
+            .answers
+            .iter()
+            .filter(|rec| matches!(rec, DnsRecord::DS { domain, .. } if domain == zone))
+            .cloned()
+            .collect();
+        if !ds.is_empty() {
+            return Ok(Some(ds));
+        }
+
+        // Without a DS the name has to be an unsigned delegation, otherwise the signer is made up
+        match dnssec::proves_insecure_delegation(&response.authorities, zone) {
+            true => Ok(None),
+            false => Err(DnsServerError::Bogus {
+                qname: zone.to_string(),
+                reason: "no DS and no proof of an unsigned delegation".to_string(),
+            }),
+        }
+    }
+
+    // Validated keys of the zone, None when the zone is provably unsigned
+    fn zone_keys(
+        &self,
+        zone: &Name,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<Option<Vec<DnsRecord>>> {
+        let response = self.recursive_lookup(zone, QueryType::DNSKEY, false, budget)?;
+        if !response.header.authed_data {
+            return Ok(None);
+        }
 
-            let recursive_response = self.recursive_lookup(&new_ns_name, QueryType::A)?;
+        Ok(Some(
+            response
+                .answers
+                .into_iter()
+                .filter(|rec| matches!(rec, DnsRecord::DNSKEY { domain, .. } if domain == zone))
+                .collect(),
+        ))
+    }
+
+    // Returns the signature which verifies the set, None when its zone is provably unsigned.
+    // Unsigned sets are checked against the zone they came from, which has to be unsigned too
+    fn verify_rrset<'a>(
+        &self,
+        rrset: &RrSet<'a>,
+        zone: &Name,
+        now: u32,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<Option<&'a DnsRecord>> {
+        let signer = rrset
+            .signatures
+            .iter()
+            .find_map(|rrsig| match rrsig {
+                DnsRecord::RRSIG { signer_name, .. }
+                    if rrset.owner.is_subdomain_of(signer_name) =>
+                {
+                    Some(signer_name)
+                }
+                _ => None,
+            })
+            .unwrap_or(zone);
+
+        let Some(keys) = self.zone_keys(signer, budget)? else {
+            return Ok(None);
+        };
+
+        dnssec::verify_rrset(rrset, &keys, now)
+            .map(Some)
+            .map_err(|reason| DnsServerError::Bogus {
+                qname: rrset.owner.to_string(),
+                reason,
+            })
+    }
+
+    // Done at startup and whenever the primed root NS set expires, returns the root servers to
+    // use. Workers which come while the root is being primed use the hints
+    pub fn prime(&self) -> Vec<IpAddr> {
+        let mut last_failure = match self.priming.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return self.root_hints.clone(),
+        };
+        if last_failure.is_some_and(|failed| failed.elapsed() < PRIMING_RETRY_INTERVAL) {
+            return self.root_hints.clone();
+        }
+
+        match self.priming_query() {
+            Ok(addrs) => {
+                *last_failure = None;
+                addrs
+            }
+            Err(e) => {
+                log::warn!("priming the root failed, using the root hints - {}", e);
+                *last_failure = Some(Instant::now());
+                self.root_hints.clone()
+            }
+        }
+    }
+
+    // RFC 8109, asks the root hints for the current NS set of the root. It's cached with the
+    // servers' addresses like any other delegation, recursion starts from it until it expires
+    fn priming_query(&self) -> DnsServerResult<Vec<IpAddr>> {
+        let root = Name::root();
+        let mut response = self.query_name_servers(
+            &self.name_server_addrs(&self.root_hints),
+            &root,
+            QueryType::NS,
+        )?;
+        log::trace!("priming response - {:?}", response);
+
+        response.sanitize(&root, &root);
+        let addrs = response.get_resolved_ns(&root, self.ip_preference);
+        if response.header.rescode != ResultCode::NOERROR || addrs.is_empty() {
+            return Err(DnsServerError::PrimingFailed);
+        }
+
+        self.cache_response(&root, QueryType::NS, &response);
+        log::info!("primed the root with {} name server addresses", addrs.len());
+
+        Ok(addrs)
+    }
+
+    // A host which can't be resolved is skipped, but running out of the budget ends the query
+    fn resolve_next_ns(
+        &self,
+        hosts: &mut Vec<Name>,
+        budget: &LookupBudget,
+    ) -> DnsServerResult<Option<Vec<IpAddr>>> {
+        while !hosts.is_empty() {
+            let host = hosts.remove(0);
+
+            // The other family is looked up only when the preferred one has no addresses
+            for query_type in self.ip_preference.address_types() {
+                match self.recursive_lookup(&host, *query_type, false, budget) {
+                    Ok(response) => {
+                        let addrs = response.get_addresses();
+                        if !addrs.is_empty() {
+                            return Ok(Some(addrs));
+                        }
+                    }
+                    Err(e @ DnsServerError::TooManyLookups { .. }) => return Err(e),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // The preferred address family goes first, within it the fastest servers
+    fn name_server_addrs(&self, addrs: &[IpAddr]) -> Vec<SocketAddr> {
+        let mut servers: Vec<SocketAddr> = addrs
+            .iter()
+            .map(|addr| (*addr, self.name_server_port).into())
+            .collect();
+        self.server_stats.sort_fastest(&mut servers);
+        self.ip_preference.apply(&mut servers);
+
+        servers
+    }
+
+    // Asks the servers one by one, servers which didn't answer in time get another chance
+    // with doubled timeout, those which answered with an error are not asked again
+    fn query_name_servers(
+        &self,
+        servers: &[SocketAddr],
+        qname: &Name,
+        query_type: QueryType,
+    ) -> DnsServerResult<DnsPacket> {
+        let mut remaining = servers.to_vec();
+        let mut timeout = self.initial_timeout;
+
+        for _ in 0..self.query_rounds {
+            let mut timed_out = Vec::new();
+
+            for server in remaining {
+                let started = Instant::now();
+                query_log::record_upstream(server);
+                let result = self.lookup(server, qname, query_type, timeout);
+
+                match &result {
+                    Err(e) if e.is_timeout() => {
+                        self.server_stats.record_failure(server, timeout);
+                        self.metrics.record_upstream_timeout();
+                    }
+                    Err(DnsServerError::PacketIdCorrupted { .. }) => {
+                        self.server_stats.record_success(server, started.elapsed());
+                        self.metrics.record_packet_id_corrupted();
+                    }
+                    _ => self.server_stats.record_success(server, started.elapsed()),
+                }
+
+                match result {
+                    Ok(response)
+                        if matches!(
+                            response.header.rescode,
+                            ResultCode::SERVFAIL | ResultCode::REFUSED | ResultCode::NOTIMP
+                        ) =>
+                    {
+                        log::warn!("{} answered {:?}", server, response.header.rescode);
+                    }
+                    Ok(response) => return Ok(response),
+                    Err(e) if e.is_timeout() => {
+                        log::warn!("{} didn't answer in {:?}", server, timeout);
+                        timed_out.push(server);
+                    }
+                    Err(e) => log::warn!("lookup at {} failed - {}", server, e),
+                }
+            }
+
+            remaining = timed_out;
+            timeout *= 2;
+        }
+
+        Err(DnsServerError::NameServersExhausted {
+            qname: qname.to_string(),
+        })
+    }
 
-            if let Some(new_ns) = recursive_response.get_first_a_record() {
-                name_server = new_ns;
-            } else {
+    // Forwarded queries are recursive for the upstream, its answer is used as is
+    fn forward_lookup(
+        &self,
+        upstreams: &UpstreamGroup,
+        qname: &Name,
+        query_type: QueryType,
+    ) -> DnsServerResult<DnsPacket> {
+        if let Some(packet) = self.cached_lookup(qname, query_type) {
+            return Ok(packet);
+        }
+
+        let servers = upstreams.ordered(&self.server_stats);
+        log::debug!("forwarding {} {} to {:?}", query_type, qname, servers);
+
+        let mut response = self.query_name_servers(&servers, qname, query_type)?;
+        log::trace!("response - {:?}", response);
+
+        // Upstreams answer for the whole tree, still only what is about the qname is kept
+        response.sanitize(qname, &Name::root());
+        self.cache_response(qname, query_type, &response);
+
+        Ok(response)
+    }
+
+    // Follows CNAMEs across zones, the answer holds the whole chain and the records it ends at.
+    // It's secure only when every link of the chain is
+    pub fn resolve(
+        &self,
+        qname: &Name,
+        query_type: QueryType,
+        checking_disabled: bool,
+    ) -> DnsServerResult<DnsPacket> {
+        let mut chain: Vec<DnsRecord> = Vec::new();
+        let mut name = qname.clone();
+        let mut secure = true;
+        let budget = LookupBudget::new();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let mut response = match self.forwarder.upstreams_for(&name) {
+                Some(upstreams) => self.forward_lookup(upstreams, &name, query_type)?,
+                None => self.recursive_lookup(&name, query_type, checking_disabled, &budget)?,
+            };
+            secure &= response.header.authed_data;
+
+            // The answer may already carry a part of the chain, or all of it
+            let (end, complete) = response.follow_cnames(&name, query_type);
+            chain.append(&mut response.answers);
+
+            // NXDOMAIN and NODATA of the chain's end are the answer for the whole chain (RFC 6604)
+            if complete || end == name || response.header.rescode != ResultCode::NOERROR {
+                response.answers = chain;
+                response.header.authed_data = secure;
                 return Ok(response);
             }
+
+            let looped = chain
+                .iter()
+                .any(|rec| matches!(rec, DnsRecord::CNAME { .. }) && *rec.domain() == end);
+            if looped {
+                log::warn!("CNAME chain of {} loops at {}", qname, end);
+                return Ok(Self::broken_chain(chain));
+            }
+
+            log::debug!("following CNAME {} -> {}", name, end);
+            name = end;
+        }
+
+        log::warn!("CNAME chain of {} is too long, giving up", qname);
+
+        Ok(Self::broken_chain(chain))
+    }
+
+    fn broken_chain(chain: Vec<DnsRecord>) -> DnsPacket {
+        let mut packet = DnsPacket::default();
+        packet.header.rescode = ResultCode::SERVFAIL;
+        packet.answers = chain;
+
+        packet
+    }
+
+    fn cache_response(&self, qname: &Name, query_type: QueryType, response: &DnsPacket) {
+        // With a CNAME in the answer the negative part is about its target, not the qname
+        let (end, complete) = response.follow_cnames(qname, query_type);
+        // AD is set only by our own validation
+        let secure = response.header.authed_data;
+
+        match response.header.rescode {
+            ResultCode::NXDOMAIN => {
+                self.cache().insert(&response.answers, secure);
+                if response.get_soa().is_some() {
+                    self.cache()
+                        .insert_nxdomain(&end, &response.authorities, secure);
+                }
+                return;
+            }
+            ResultCode::NOERROR => {}
+            _ => return,
+        }
+
+        // NODATA, the name exists but has no records of the requested type
+        if !complete && response.get_soa().is_some() {
+            self.cache()
+                .insert_nodata(&end, query_type, &response.authorities, secure);
         }
+
+        self.cache().insert(&response.answers, secure);
+
+        // Delegations, so the next lookup in the same zone can skip the upper levels
+        let delegations: Vec<DnsRecord> = response
+            .authorities
+            .iter()
+            .filter(|record| matches!(record, DnsRecord::NS { .. }))
+            .cloned()
+            .collect();
+        self.cache().insert(&delegations, false);
+
+        let glue: Vec<DnsRecord> = response
+            .resources
+            .iter()
+            .filter(|record| matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. }))
+            .cloned()
+            .collect();
+        self.cache().insert(&glue, false);
     }
+
     fn lookup(
-        &mut self,
-        server: (Ipv4Addr, u16),
-        qname: &str,
+        &self,
+        server: SocketAddr,
+        qname: &Name,
         qtype: QueryType,
+        timeout: Duration,
     ) -> DnsServerResult<DnsPacket> {
-        let socket = UdpSocket::bind(("0.0.0.0", self.port))?;
+        let mut randomize_case = self.randomize_case && self.server_stats.echoes_case(server);
+
+        let response = match self.query_server(server, qname, qtype, timeout, true, randomize_case)
+        {
+            Err(DnsServerError::CaseMismatch { .. }) => {
+                log::debug!(
+                    "{} didn't echo the case of {}, retrying without 0x20",
+                    server,
+                    qname
+                );
+                randomize_case = false;
+                self.query_server(server, qname, qtype, timeout, true, randomize_case)?
+            }
+            result => result?,
+        };
+
+        // Old servers answer FORMERR to anything carrying OPT, RFC 6891 says to retry without it
+        if response.header.rescode == ResultCode::FORMERR && response.get_edns().is_none() {
+            log::info!("{} doesn't support EDNS(0), retrying without it", server);
+            return self.query_server(server, qname, qtype, timeout, false, randomize_case);
+        }
+
+        Ok(response)
+    }
+
+    fn query_server(
+        &self,
+        server: SocketAddr,
+        qname: &Name,
+        qtype: QueryType,
+        timeout: Duration,
+        edns: bool,
+        randomize_case: bool,
+    ) -> DnsServerResult<DnsPacket> {
+        let socket = bind_random_port(&server)?;
 
         let mut packet = DnsPacket::default();
-        let id: u16 = self.rng.gen();
+        let id: u16 = rand::thread_rng().gen();
+
+        // 0x20, a spoofer has to guess the case of every letter along with the id and the port
+        let sent_name = if randomize_case {
+            qname.randomize_case(&mut rand::thread_rng())
+        } else {
+            qname.clone()
+        };
 
         packet.header.id = id;
         packet.header.questions = 1;
         packet.header.recursion_desired = true;
         packet
             .questions
-            .push(DnsQuestion::new(qname.to_string(), qtype));
+            .push(DnsQuestion::new(sent_name.clone(), qtype));
+
+        if edns {
+            packet.resources.push(DnsRecord::OPT {
+                packet_len: MAX_UDP_PAYLOAD_SIZE as u16,
+                extended_rcode: 0,
+                version: 0,
+                // The signatures and NSEC records are needed for the validation
+                dnssec_ok: self.trust_anchors.is_some(),
+                options: Vec::new(),
+            });
+        }
 
         let mut req_buffer = BytePacketBuffer::new();
         packet.write(&mut req_buffer)?;
         socket.send_to(&req_buffer.buff[0..req_buffer.pos], server)?;
 
-        let mut res_buffer = BytePacketBuffer::new();
-        socket.recv_from(&mut res_buffer.buff)?;
+        let mut res_buffer = BytePacketBuffer::with_size(MAX_UDP_PAYLOAD_SIZE);
+        let deadline = Instant::now() + timeout;
+
+        // Anybody can send to our port, so datagrams which didn't come from the server we asked are dropped
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            let (_, src) = socket.recv_from(&mut res_buffer.buff)?;
+            if src == server {
+                break;
+            }
+
+            log::warn!(
+                "dropping answer from {}, the query was sent to {}",
+                src,
+                server
+            );
+        }
 
-        let result_packet = DnsPacket::from_buffer(&mut res_buffer)?;
+        // A truncated answer may end in the middle of a record, so look only at the header first
+        let mut header = DnsHeader::default();
+        header.read(&mut res_buffer)?;
+        res_buffer.seek(0);
+
+        let mut result_packet = if header.truncated_message {
+            log::debug!("answer from {} is truncated, retrying over tcp", server);
+            self.lookup_tcp(server, &req_buffer, timeout)?
+        } else {
+            DnsPacket::from_buffer(&mut res_buffer)?
+        };
 
         if result_packet.header.id != id {
             return Err(DnsServerError::PacketIdCorrupted {
@@ -96,66 +1033,543 @@ impl DnsServer {
             });
         }
 
+        // Only our own validation may set AD, what the upstream claims means nothing
+        result_packet.header.authed_data = false;
+
+        // The question has to be echoed back, only servers which can't parse our query may omit it
+        let question_matches = match result_packet.questions.as_slice() {
+            [question] => question.name == *qname && question.query_type == qtype,
+            [] => result_packet.header.rescode == ResultCode::FORMERR,
+            _ => false,
+        };
+        if !question_matches {
+            return Err(DnsServerError::QuestionMismatch {
+                qname: qname.to_string(),
+            });
+        }
+
+        if !randomize_case {
+            return Ok(result_packet);
+        }
+
+        if let Some(question) = result_packet.questions.first_mut() {
+            // Either a spoofed answer or a server which doesn't keep the case. We can't tell
+            // them apart from one answer, repeated ones turn 0x20 off for the server for a while
+            if !question.name.eq_case_sensitive(&sent_name) {
+                self.server_stats.record_case_mismatch(server);
+                return Err(DnsServerError::CaseMismatch {
+                    qname: qname.to_string(),
+                });
+            }
+            question.name = qname.clone();
+        }
+
+        // Clients see the name as they asked for it, not in our random case
+        for rec in result_packet
+            .answers
+            .iter_mut()
+            .chain(result_packet.authorities.iter_mut())
+            .chain(result_packet.resources.iter_mut())
+        {
+            if rec.domain().eq_case_sensitive(&sent_name) {
+                rec.set_domain(qname.clone());
+            }
+        }
+
         Ok(result_packet)
     }
 
-    pub fn handle_query(&mut self, socket: &UdpSocket) -> DnsServerResult<()> {
-        let mut req_buffer = BytePacketBuffer::new();
+    fn lookup_tcp(
+        &self,
+        server: SocketAddr,
+        req_buffer: &BytePacketBuffer,
+        timeout: Duration,
+    ) -> DnsServerResult<DnsPacket> {
+        let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        // Over tcp every message is prefixed with its length as u16
+        stream.write_all(&(req_buffer.pos as u16).to_be_bytes())?;
+        stream.write_all(&req_buffer.buff[0..req_buffer.pos])?;
 
-        let (_, src) = socket.recv_from(&mut req_buffer.buff)?;
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+
+        let mut res_buffer = BytePacketBuffer::with_size(MAX_MESSAGE_SIZE);
+        stream.read_exact(&mut res_buffer.buff[0..len])?;
+
+        Ok(DnsPacket::from_buffer(&mut res_buffer)?)
+    }
+
+    pub fn handle_query(&self, request: DnsPacket) -> DnsPacket {
+        let started = Instant::now();
+        let query_type = request
+            .questions
+            .first()
+            .map(|question| question.query_type);
+
+        // The cache is counted once per client query, not for the lookups of the recursion itself.
+        // Without a query log the listener doesn't trace, the trace is started here then
+        let traced = query_log::is_tracing();
+        if !traced {
+            query_log::begin();
+        }
+        let packet = self.answer_query(request);
+        let cache_hit = if traced {
+            query_log::cache_hit()
+        } else {
+            query_log::finish().cache_hit
+        };
+
+        self.metrics
+            .record_query(query_type, packet.header.rescode, started.elapsed());
+        if let Some(hit) = cache_hit {
+            self.metrics.record_cache_lookup(hit);
+        }
 
-        let mut request = DnsPacket::from_buffer(&mut req_buffer)?;
+        packet
+    }
 
+    fn answer_query(&self, mut request: DnsPacket) -> DnsPacket {
         let mut packet = DnsPacket::default();
         packet.header.id = request.header.id;
         packet.header.recursion_desired = true;
-        packet.header.recursion_available = true;
+        packet.header.recursion_available = self.recursion;
         packet.header.response = true;
+        packet.header.checking_disabled = request.header.checking_disabled;
+
+        // AD only for clients which can make use of it (RFC 6840 section 5.8)
+        let dnssec_ok = matches!(
+            request.get_edns(),
+            Some(DnsRecord::OPT {
+                dnssec_ok: true,
+                ..
+            })
+        );
+        let wants_ad = dnssec_ok || request.header.authed_data;
+
+        // Clients which speak EDNS(0) get our OPT back
+        if let Some(DnsRecord::OPT {
+            version, dnssec_ok, ..
+        }) = request.get_edns()
+        {
+            packet.resources.push(DnsRecord::OPT {
+                packet_len: MAX_UDP_PAYLOAD_SIZE as u16,
+                // 1 in the upper bits of the rcode is BADVERS, we know only version 0
+                extended_rcode: (*version > 0) as u8,
+                version: 0,
+                dnssec_ok: *dnssec_ok,
+                options: Vec::new(),
+            });
+
+            if *version > 0 {
+                packet.questions = request.questions;
+                return packet;
+            }
+        }
+
+        // Only standard queries are served, dynamic updates (RFC 2136) and the rest get NOTIMP
+        if request.header.opcode != 0 {
+            log::info!("Received opcode {}, not implemented", request.header.opcode);
+            packet.header.opcode = request.header.opcode;
+            packet.header.rescode = ResultCode::NOTIMP;
+            packet.questions = request.questions;
+            return packet;
+        }
+
+        let Some(question) = request.questions.pop() else {
+            packet.header.rescode = ResultCode::FORMERR;
+            return packet;
+        };
+
+        log::info!("Received query: {:?}", question);
+
+        // ANY is served from the IN data, the only data we have besides the CHAOS identity
+        match question.query_class {
+            QueryClass::IN | QueryClass::ANY => {}
+            QueryClass::CH => {
+                let result = self.identity.lookup(&question.name, question.query_type);
+                packet.questions.push(question);
 
-        if let Some(question) = request.questions.pop() {
-            println!("Received query: {:?}", question);
+                packet.header.authoritative_answer = result.header.authoritative_answer;
+                packet.header.rescode = result.header.rescode;
+                packet.answers = result.answers;
 
-            if let Ok(result) = self.recursive_lookup(&question.name, question.query_type) {
+                return packet;
+            }
+            _ => {
                 packet.questions.push(question);
+                packet.header.rescode = ResultCode::REFUSED;
+                return packet;
+            }
+        }
+
+        if let Some(zone) = self.zones.find(&question.name) {
+            let result = zone.lookup(&question.name, question.query_type, dnssec_ok);
+            packet.questions.push(question);
+
+            packet.header.authoritative_answer = result.header.authoritative_answer;
+            packet.header.rescode = result.header.rescode;
+            packet.answers = result.answers;
+            packet.authorities = result.authorities;
+            packet.resources.extend(result.resources);
+
+            return packet;
+        }
+
+        // Our own zones are never blocked, everything the recursion would answer may be
+        if let Some(result) = self
+            .blocklist
+            .as_ref()
+            .and_then(|blocklist| blocklist.lookup(&question.name, question.query_type))
+        {
+            packet.questions.push(question);
+
+            packet.header.rescode = result.header.rescode;
+            packet.answers = result.answers;
+
+            return packet;
+        }
+
+        let query_type = question.query_type;
+
+        if !self.recursion {
+            let result = self.cached_lookup(&question.name, question.query_type);
+            query_log::record_cache_hit(result.is_some());
+            packet.questions.push(question);
+
+            match result {
+                Some(mut result) => {
+                    if !dnssec_ok {
+                        result.strip_dnssec(query_type);
+                    }
+                    packet.header.rescode = result.header.rescode;
+                    packet.header.authed_data = result.header.authed_data && wants_ad;
+                    packet.answers = result.answers;
+                    packet.authorities = result.authorities;
+                }
+                None => packet.header.rescode = ResultCode::REFUSED,
+            }
+
+            return packet;
+        }
+
+        // Above the limit we answer right away instead of piling up more work
+        let Some(_permit) = self.in_flight.try_acquire() else {
+            log::warn!("Too many recursions in flight, refusing {:?}", question);
+            packet.questions.push(question);
+            packet.header.rescode = ResultCode::SERVFAIL;
+            return packet;
+        };
+
+        let result = self.resolve(
+            &question.name,
+            question.query_type,
+            request.header.checking_disabled,
+        );
+        query_log::record_resolved();
+        packet.questions.push(question);
+
+        match result {
+            Ok(mut result) => {
+                if !dnssec_ok {
+                    result.strip_dnssec(query_type);
+                }
                 packet.header.rescode = result.header.rescode;
+                packet.header.authed_data = result.header.authed_data && wants_ad;
 
                 for rec in result.answers {
-                    println!("Answer: {:?}", rec);
+                    log::debug!("Answer: {}", rec);
                     packet.answers.push(rec);
                 }
                 for rec in result.authorities {
-                    println!("Authority: {:?}", rec);
+                    log::debug!("Authority: {}", rec);
                     packet.authorities.push(rec);
                 }
-                for rec in result.resources {
-                    println!("Resource: {:?}", rec);
+                // OPT of the upstream server is hop by hop, it must not be forwarded to the client
+                for rec in result
+                    .resources
+                    .into_iter()
+                    .filter(|rec| !matches!(rec, DnsRecord::OPT { .. }))
+                {
+                    log::debug!("Resource: {}", rec);
                     packet.resources.push(rec);
                 }
-            } else {
+            }
+            Err(e) => {
+                log::error!("Lookup failed - {}", e);
                 packet.header.rescode = ResultCode::SERVFAIL;
             }
-        } else {
-            packet.header.rescode = ResultCode::FORMERR;
         }
 
-        let mut res_buffer = BytePacketBuffer::new();
-        packet.write(&mut res_buffer)?;
+        packet
+    }
+}
 
-        socket.send_to(&res_buffer.buff[0..res_buffer.pos], src)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DnssecConfig,
+        test_zone::{self, name, ORIGIN},
+        zone::Zone,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
 
-        Ok(())
+    // A server trusting the KSK of the zone, with the zone's keys validated and cached so
+    // nothing has to be asked upstream
+    fn validating_server(zone: &Zone) -> (DnsServer, TrustAnchors) {
+        let origin = name(ORIGIN);
+        let keys = test_zone::dnskeys(zone);
+        let ds: Vec<String> = keys
+            .iter()
+            .filter(|key| matches!(key, DnsRecord::DNSKEY { flags, .. } if flags & 0x0001 != 0))
+            .filter_map(|key| dnssec::ds_of(key, dnssec::SHA256))
+            .map(|ds| ds.to_string())
+            .collect();
+        let trust_anchors = TrustAnchors::load(&DnssecConfig {
+            validate: true,
+            trust_anchors: ds,
+            trust_anchor_file: None,
+        })
+        .unwrap();
+
+        let server = DnsServer::builder(&Config::default()).build();
+        let response = zone.lookup(&origin, QueryType::DNSKEY, true);
+        assert!(server
+            .validate(
+                &trust_anchors,
+                &response,
+                &origin,
+                QueryType::DNSKEY,
+                &origin,
+                &LookupBudget::new()
+            )
+            .unwrap());
+        server.cache().insert(&response.answers, true);
+
+        (server, trust_anchors)
     }
-}
 
-impl Default for DnsServer {
-    fn default() -> Self {
-        let rng = rand::thread_rng();
+    fn validate(
+        server: &DnsServer,
+        trust_anchors: &TrustAnchors,
+        response: &DnsPacket,
+        qname: &str,
+        query_type: QueryType,
+    ) -> DnsServerResult<bool> {
+        server.validate(
+            trust_anchors,
+            response,
+            &name(qname),
+            query_type,
+            &name(ORIGIN),
+            &LookupBudget::new(),
+        )
+    }
 
-        Self {
-            // For now we're always starting with *a.root-servers.net*.
-            root: "198.41.0.4".parse::<Ipv4Addr>().unwrap(),
-            port: 43210,
-            rng,
+    #[test]
+    fn answers_of_a_signed_zone_are_secure() {
+        for nsec3 in [false, true] {
+            let zone = test_zone::zone(nsec3);
+            let (server, trust_anchors) = validating_server(&zone);
+
+            for (qname, query_type) in [
+                ("www.example.", QueryType::A),
+                ("x.wild.example.", QueryType::A),
+                ("nx.example.", QueryType::A),
+                ("www.example.", QueryType::MX),
+                ("b.example.", QueryType::A),
+            ] {
+                let response = zone.lookup(&name(qname), query_type, true);
+                assert!(
+                    validate(&server, &trust_anchors, &response, qname, query_type).unwrap(),
+                    "{} {} with nsec3 {}",
+                    qname,
+                    query_type,
+                    nsec3
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_answers_are_bogus() {
+        let zone = test_zone::zone(false);
+        let (server, trust_anchors) = validating_server(&zone);
+
+        let mut response = zone.lookup(&name("www.example."), QueryType::A, true);
+        for record in &mut response.answers {
+            if let DnsRecord::A { addr, .. } = record {
+                *addr = Ipv4Addr::new(192, 0, 2, 66);
+            }
         }
+        assert!(matches!(
+            validate(
+                &server,
+                &trust_anchors,
+                &response,
+                "www.example.",
+                QueryType::A
+            ),
+            Err(DnsServerError::Bogus { .. })
+        ));
+    }
+
+    #[test]
+    fn denials_without_proof_are_bogus() {
+        let zone = test_zone::zone(false);
+        let (server, trust_anchors) = validating_server(&zone);
+
+        let mut response = zone.lookup(&name("nx.example."), QueryType::A, true);
+        response
+            .authorities
+            .retain(|record| !matches!(record, DnsRecord::NSEC { .. }));
+        assert!(matches!(
+            validate(
+                &server,
+                &trust_anchors,
+                &response,
+                "nx.example.",
+                QueryType::A
+            ),
+            Err(DnsServerError::Bogus { .. })
+        ));
+    }
+
+    #[test]
+    fn keys_the_trust_anchor_doesnt_match_are_bogus() {
+        let zone = test_zone::zone(false);
+        let (server, trust_anchors) = validating_server(&zone);
+
+        let origin = name(ORIGIN);
+        let other = test_zone::zone(false).lookup(&origin, QueryType::DNSKEY, true);
+        assert!(matches!(
+            server.validate(
+                &trust_anchors,
+                &other,
+                &origin,
+                QueryType::DNSKEY,
+                &origin,
+                &LookupBudget::new()
+            ),
+            Err(DnsServerError::Bogus { .. })
+        ));
+    }
+
+    // Refers a.test to ns.b.test and b.test to ns.a.test, both without glue, and refuses the
+    // rest. Returns its port and how many queries it got
+    fn cyclic_name_server() -> (u16, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+
+        thread::spawn(move || loop {
+            let mut buffer = BytePacketBuffer::new();
+            let Ok((_, client)) = socket.recv_from(&mut buffer.buff) else {
+                return;
+            };
+            let Ok(request) = DnsPacket::from_buffer(&mut buffer) else {
+                continue;
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+
+            let mut response = DnsPacket::default();
+            response.header.id = request.header.id;
+            response.header.response = true;
+            response.header.rescode = ResultCode::REFUSED;
+            response.questions = request.questions.clone();
+
+            let qname = &request.questions[0].name;
+            for (zone, host) in [("a.test.", "ns.b.test."), ("b.test.", "ns.a.test.")] {
+                if qname.is_subdomain_of(&name(zone)) {
+                    response.header.rescode = ResultCode::NOERROR;
+                    response.authorities.push(DnsRecord::NS {
+                        domain: name(zone),
+                        class: QueryClass::IN,
+                        host: name(host),
+                        ttl: 3600,
+                    });
+                }
+            }
+
+            let mut buffer = BytePacketBuffer::new();
+            response.write(&mut buffer).unwrap();
+            let _ = socket.send_to(&buffer.buff[..buffer.pos], client);
+        });
+
+        (port, queries)
+    }
+
+    #[test]
+    fn delegations_whose_name_servers_are_under_each_other_fail() {
+        let (port, queries) = cyclic_name_server();
+        let config = Config {
+            root_hints: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            ..Config::default()
+        };
+        let server = DnsServer::builder(&config).name_server_port(port).build();
+
+        assert!(matches!(
+            server.resolve(&name("www.a.test."), QueryType::A, false),
+            Err(DnsServerError::TooManyLookups { .. })
+        ));
+        // Each lookup asks once, plus the failed priming
+        assert!(queries.load(Ordering::Relaxed) <= MAX_LOOKUPS + 1);
+    }
+
+    fn query(qname: &str, query_type: QueryType, query_class: QueryClass) -> DnsPacket {
+        let mut request = DnsPacket::default();
+        request.header.id = 1;
+        request.questions.push(DnsQuestion {
+            name: name(qname),
+            query_type,
+            query_class,
+        });
+        request
+    }
+
+    #[test]
+    fn questions_of_class_any_are_answered_from_the_in_data() {
+        let server = DnsServer::builder(&Config {
+            recursion: false,
+            ..Config::default()
+        })
+        .build();
+        server.cache().insert(
+            &[DnsRecord::A {
+                domain: name("www.example."),
+                class: QueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 10),
+                ttl: 3600,
+            }],
+            false,
+        );
+
+        let response = server.handle_query(query("www.example.", QueryType::A, QueryClass::ANY));
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.questions[0].query_class, QueryClass::ANY);
+    }
+
+    // Dynamic updates (RFC 2136) aren't supported, neither is any other opcode but QUERY
+    #[test]
+    fn updates_get_notimp() {
+        let server = DnsServer::builder(&Config::default()).build();
+        let mut request = query("example.", QueryType::SOA, QueryClass::IN);
+        request.header.opcode = 5;
+
+        let response = server.handle_query(request);
+        assert_eq!(response.header.rescode, ResultCode::NOTIMP);
+        assert_eq!(response.header.opcode, 5);
+        assert!(response.answers.is_empty());
     }
 }
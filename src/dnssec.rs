@@ -0,0 +1,1067 @@
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::{digest, signature};
+
+use crate::{
+    byte_packet_buffer::{BytePacketBuffer, MAX_MESSAGE_SIZE},
+    config::DnssecConfig,
+    dns_records::{base32hex_decode, DnsRecord},
+    errors::{ZoneError, ZoneResult},
+    name::Name,
+    query_type::QueryType,
+    zone_file::ZoneFileParser,
+};
+
+// DNSKEY algorithms we can verify, the ones RFC 8624 says a validator must support
+pub const RSASHA256: u8 = 8;
+pub const ECDSAP256SHA256: u8 = 13;
+pub const ED25519: u8 = 15;
+
+// DS digest types
+pub const SHA1: u8 = 1;
+pub const SHA256: u8 = 2;
+pub const SHA384: u8 = 4;
+
+// Only keys with the zone key bit may sign (RFC 4034 section 2.1.1)
+pub const ZONE_KEY: u16 = 0x0100;
+const DNSKEY_PROTOCOL: u8 = 3;
+
+pub const NSEC3_SHA1: u8 = 1;
+pub const NSEC3_OPT_OUT: u8 = 0x01;
+// NSEC3 of zones using more iterations are taken as insecure instead of spending the time on
+// hashing (RFC 9276 section 3.2)
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+// The root KSKs as published by IANA in root-anchors.xml
+const ROOT_TRUST_ANCHORS: [&str; 2] = [
+    ". IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+    ". IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+];
+
+pub fn default_trust_anchors() -> Vec<String> {
+    ROOT_TRUST_ANCHORS.iter().map(|ds| ds.to_string()).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Security {
+    Secure,
+    // Provably unsigned, e.g. an opt-out NSEC3 covers the name
+    Insecure,
+    Bogus(String),
+}
+
+// DS records the chains of trust start from. Keys given as DNSKEY are turned into their DS
+pub struct TrustAnchors {
+    anchors: Vec<DnsRecord>,
+}
+
+impl TrustAnchors {
+    pub fn load(config: &DnssecConfig) -> ZoneResult<Self> {
+        let (path, content) = match &config.trust_anchor_file {
+            Some(path) => (
+                path.as_path(),
+                fs::read_to_string(path).map_err(|error| ZoneError::Read {
+                    path: path.to_path_buf(),
+                    error,
+                })?,
+            ),
+            None => (Path::new("trust_anchors"), config.trust_anchors.join("\n")),
+        };
+        // The TTL of an anchor means nothing, they don't expire
+        let records =
+            ZoneFileParser::new(path, &Name::root()).parse(&format!("$TTL 0\n{}", content))?;
+
+        let mut anchors = Vec::new();
+        for record in records {
+            match record {
+                DnsRecord::DS { .. } => anchors.push(record),
+                DnsRecord::DNSKEY { .. } => {
+                    anchors.push(ds_of(&record, SHA256).ok_or_else(|| ZoneError::Invalid {
+                        origin: record.domain().to_string(),
+                        reason: "trust anchor isn't a DNSSEC zone key".to_string(),
+                    })?)
+                }
+                _ => {
+                    return Err(ZoneError::Invalid {
+                        origin: record.domain().to_string(),
+                        reason: format!(
+                            "trust anchors are DS or DNSKEY records, not {:?}",
+                            record.query_type()
+                        ),
+                    })
+                }
+            }
+        }
+
+        if anchors.is_empty() {
+            return Err(ZoneError::Invalid {
+                origin: Name::root().to_string(),
+                reason: format!("{:?} has no trust anchors", path),
+            });
+        }
+
+        Ok(TrustAnchors { anchors })
+    }
+
+    // The DS records configured for exactly this zone
+    pub fn ds_set(&self, zone: &Name) -> Vec<DnsRecord> {
+        self.anchors
+            .iter()
+            .filter(|ds| ds.domain() == zone)
+            .cloned()
+            .collect()
+    }
+
+    // Names outside of every anchor can't be validated, they're insecure
+    pub fn covers(&self, name: &Name) -> bool {
+        self.anchors
+            .iter()
+            .any(|ds| name.is_subdomain_of(ds.domain()))
+    }
+}
+
+// Records of one owner and type together with the RRSIGs that cover them
+pub struct RrSet<'a> {
+    pub owner: &'a Name,
+    pub query_type: QueryType,
+    pub records: Vec<&'a DnsRecord>,
+    pub signatures: Vec<&'a DnsRecord>,
+}
+
+pub fn rrsets(records: &[DnsRecord]) -> Vec<RrSet<'_>> {
+    let mut sets: Vec<RrSet> = Vec::new();
+    for record in records {
+        if matches!(record, DnsRecord::RRSIG { .. } | DnsRecord::OPT { .. }) {
+            continue;
+        }
+        match sets
+            .iter_mut()
+            .find(|set| set.owner == record.domain() && set.query_type == record.query_type())
+        {
+            Some(set) => set.records.push(record),
+            None => sets.push(RrSet {
+                owner: record.domain(),
+                query_type: record.query_type(),
+                records: vec![record],
+                signatures: Vec::new(),
+            }),
+        }
+    }
+
+    for set in &mut sets {
+        set.signatures = records
+            .iter()
+            .filter(|record| {
+                matches!(record, DnsRecord::RRSIG { domain, type_covered, .. }
+                    if domain == set.owner && *type_covered == set.query_type)
+            })
+            .collect();
+    }
+
+    sets
+}
+
+pub fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, RSASHA256 | ECDSAP256SHA256 | ED25519)
+}
+
+pub fn is_supported_ds(ds: &DnsRecord) -> bool {
+    matches!(ds, DnsRecord::DS { algorithm, digest_type, .. }
+        if is_supported_algorithm(*algorithm) && matches!(*digest_type, SHA1 | SHA256 | SHA384))
+}
+
+// Name in the lowercase, uncompressed wire form
+fn canonical_name(name: &Name) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in name.labels() {
+        wire.push(label.len() as u8);
+        wire.extend(label.to_ascii_lowercase());
+    }
+    wire.push(0);
+    wire
+}
+
+fn dnskey_rdata(key: &DnsRecord) -> Option<Vec<u8>> {
+    match key {
+        DnsRecord::DNSKEY {
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+            ..
+        } => Some(
+            [
+                &flags.to_be_bytes()[..],
+                &[*protocol, *algorithm],
+                public_key,
+            ]
+            .concat(),
+        ),
+        _ => None,
+    }
+}
+
+// RFC 4034 appendix B
+pub fn key_tag(key: &DnsRecord) -> Option<u16> {
+    let rdata = dnskey_rdata(key)?;
+    let mut sum: u32 = rdata
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            if i & 1 == 0 {
+                (*byte as u32) << 8
+            } else {
+                *byte as u32
+            }
+        })
+        .sum();
+    sum += (sum >> 16) & 0xFFFF;
+    Some((sum & 0xFFFF) as u16)
+}
+
+// The DS record the parent zone publishes for the key
+pub fn ds_of(key: &DnsRecord, digest_type: u8) -> Option<DnsRecord> {
+    let DnsRecord::DNSKEY {
+        domain,
+        class,
+        algorithm,
+        ttl,
+        ..
+    } = key
+    else {
+        return None;
+    };
+    let algorithm_digest = match digest_type {
+        SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        SHA256 => &digest::SHA256,
+        SHA384 => &digest::SHA384,
+        _ => return None,
+    };
+    let data = [canonical_name(domain), dnskey_rdata(key)?].concat();
+
+    Some(DnsRecord::DS {
+        domain: domain.clone(),
+        class: *class,
+        key_tag: key_tag(key)?,
+        algorithm: *algorithm,
+        digest_type,
+        digest: digest::digest(algorithm_digest, &data).as_ref().to_vec(),
+        ttl: *ttl,
+    })
+}
+
+pub fn ds_matches(ds: &DnsRecord, key: &DnsRecord) -> bool {
+    let DnsRecord::DS {
+        domain,
+        key_tag,
+        algorithm,
+        digest_type,
+        digest,
+        ..
+    } = ds
+    else {
+        return false;
+    };
+    matches!(ds_of(key, *digest_type), Some(DnsRecord::DS {
+            domain: key_domain,
+            key_tag: key_key_tag,
+            algorithm: key_algorithm,
+            digest: key_digest,
+            ..
+        }) if key_domain == *domain
+            && key_key_tag == *key_tag
+            && key_algorithm == *algorithm
+            && key_digest == *digest)
+}
+
+// The record as RFC 4034 section 6.2 has it signed: owner and the names in the RDATA of the
+// older types in lowercase, the TTL the signature was made with, nothing compressed
+fn canonical_record(record: &DnsRecord, owner: &Name, original_ttl: u32) -> Option<Vec<u8>> {
+    let mut record = record.clone();
+    record.set_domain(owner.to_lowercase());
+    record.set_ttl(original_ttl);
+    match &mut record {
+        DnsRecord::NS { host, .. }
+        | DnsRecord::CNAME { host, .. }
+        | DnsRecord::PTR { host, .. }
+        | DnsRecord::MX { host, .. }
+        | DnsRecord::SRV { host, .. } => *host = host.to_lowercase(),
+        DnsRecord::SOA { m_name, r_name, .. } => {
+            *m_name = m_name.to_lowercase();
+            *r_name = r_name.to_lowercase();
+        }
+        DnsRecord::NAPTR { replacement, .. } => *replacement = replacement.to_lowercase(),
+        _ => {}
+    }
+
+    let mut buffer = BytePacketBuffer::uncompressed(MAX_MESSAGE_SIZE);
+    record.write(&mut buffer).ok()?;
+    Some(buffer.buff[..buffer.pos()].to_vec())
+}
+
+// What the signature is over: its own RDATA up to the signature, then the records in canonical
+// form and order (RFC 4034 section 3.1.8.1)
+pub fn signed_data(records: &[&DnsRecord], rrsig: &DnsRecord) -> Option<Vec<u8>> {
+    let DnsRecord::RRSIG {
+        type_covered,
+        algorithm,
+        labels,
+        original_ttl,
+        expiration,
+        inception,
+        key_tag,
+        signer_name,
+        ..
+    } = rrsig
+    else {
+        return None;
+    };
+
+    let mut data = Vec::new();
+    data.extend(u16::from(*type_covered).to_be_bytes());
+    data.push(*algorithm);
+    data.push(*labels);
+    data.extend(original_ttl.to_be_bytes());
+    data.extend(expiration.to_be_bytes());
+    data.extend(inception.to_be_bytes());
+    data.extend(key_tag.to_be_bytes());
+    data.extend(canonical_name(signer_name));
+
+    // An answer expanded from a wildcard was signed as the wildcard
+    let owner = records.first()?.domain();
+    let owner = match owner.label_count().checked_sub(*labels as usize) {
+        Some(0) => owner.clone(),
+        Some(extra) => owner.suffix(extra).wildcard().ok()?,
+        None => return None,
+    };
+
+    let header = canonical_name(&owner).len() + 10;
+    let mut records = records
+        .iter()
+        .map(|record| canonical_record(record, &owner, *original_ttl))
+        .collect::<Option<Vec<_>>>()?;
+    records.sort_by(|a, b| a[header..].cmp(&b[header..]));
+    records.dedup();
+
+    for record in records {
+        data.extend(record);
+    }
+    Some(data)
+}
+
+// RFC 3110 section 2, the exponent length takes a byte, or three when the first one is 0
+fn rsa_key_parts(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = match key.split_first()? {
+        (0, rest) if rest.len() > 2 => {
+            (u16::from_be_bytes([rest[0], rest[1]]) as usize, &rest[2..])
+        }
+        (0, _) => return None,
+        (len, rest) => (*len as usize, rest),
+    };
+    (rest.len() > len).then(|| rest.split_at(len))
+}
+
+pub fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], sig: &[u8]) -> bool {
+    match algorithm {
+        RSASHA256 => match rsa_key_parts(public_key) {
+            Some((e, n)) => signature::RsaPublicKeyComponents { n, e }
+                .verify(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    data,
+                    sig,
+                )
+                .is_ok(),
+            None => false,
+        },
+        // DNSSEC leaves out the uncompressed point prefix
+        ECDSAP256SHA256 => signature::UnparsedPublicKey::new(
+            &signature::ECDSA_P256_SHA256_FIXED,
+            [&[0x04], public_key].concat(),
+        )
+        .verify(data, sig)
+        .is_ok(),
+        ED25519 => signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(data, sig)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+// Serial number arithmetic, the fields wrap around in 2106 (RFC 4034 section 3.1.5)
+fn is_current(now: u32, inception: u32, expiration: u32) -> bool {
+    now.wrapping_sub(inception) as i32 >= 0 && expiration.wrapping_sub(now) as i32 >= 0
+}
+
+// One of the signatures has to be valid right now and made by one of the keys of its signer.
+// Returns the signature that is
+pub fn verify_rrset<'a>(
+    rrset: &RrSet<'a>,
+    keys: &[DnsRecord],
+    now: u32,
+) -> Result<&'a DnsRecord, String> {
+    let mut reason = format!("{} {:?} isn't signed", rrset.owner, rrset.query_type);
+
+    for rrsig in &rrset.signatures {
+        let DnsRecord::RRSIG {
+            algorithm,
+            labels,
+            expiration,
+            inception,
+            key_tag: tag,
+            signer_name,
+            signature,
+            ..
+        } = rrsig
+        else {
+            continue;
+        };
+        // The labels field leaves out the root and a leading wildcard
+        let owner_labels = rrset.owner.label_count() - rrset.owner.is_wildcard() as usize;
+        if !rrset.owner.is_subdomain_of(signer_name) || *labels as usize > owner_labels {
+            reason = format!(
+                "{} {:?} has a malformed RRSIG",
+                rrset.owner, rrset.query_type
+            );
+            continue;
+        }
+        if !is_current(now, *inception, *expiration) {
+            reason = format!(
+                "the RRSIG of {} {:?} has expired or isn't valid yet",
+                rrset.owner, rrset.query_type
+            );
+            continue;
+        }
+        let Some(data) = signed_data(&rrset.records, rrsig) else {
+            continue;
+        };
+
+        reason = format!(
+            "no key of {} verifies the RRSIG of {} {:?}",
+            signer_name, rrset.owner, rrset.query_type
+        );
+        let verified = keys.iter().any(|key| match key {
+            DnsRecord::DNSKEY {
+                domain,
+                flags,
+                protocol: DNSKEY_PROTOCOL,
+                algorithm: key_algorithm,
+                public_key,
+                ..
+            } => {
+                domain == signer_name
+                    && flags & ZONE_KEY != 0
+                    && key_algorithm == algorithm
+                    && key_tag(key) == Some(*tag)
+                    && verify_signature(*algorithm, public_key, &data, signature)
+            }
+            _ => false,
+        });
+        if verified {
+            return Ok(rrsig);
+        }
+    }
+
+    Err(reason)
+}
+
+// The name a wildcard answer was expanded from, i.e. the closest encloser of the owner
+pub fn wildcard_source(owner: &Name, rrsig: &DnsRecord) -> Option<Name> {
+    match rrsig {
+        DnsRecord::RRSIG { labels, .. }
+            if !owner.is_wildcard() && (*labels as usize) < owner.label_count() =>
+        {
+            Some(owner.suffix(owner.label_count() - *labels as usize))
+        }
+        _ => None,
+    }
+}
+
+struct Nsec<'a> {
+    owner: &'a Name,
+    next: &'a Name,
+    types: &'a [QueryType],
+}
+
+fn nsecs(records: &[DnsRecord]) -> Vec<Nsec<'_>> {
+    records
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::NSEC {
+                domain,
+                next_domain,
+                types,
+                ..
+            } => Some(Nsec {
+                owner: domain,
+                next: next_domain,
+                types,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Nsec<'_> {
+    // The name falls between the owner and the next name in canonical order. The last NSEC of
+    // the zone points back at the apex
+    fn covers(&self, name: &Name) -> bool {
+        if self.owner < self.next {
+            self.owner < name && name < self.next
+        } else {
+            self.owner < name || name < self.next
+        }
+    }
+}
+
+struct Nsec3<'a> {
+    zone: Name,
+    hashed_owner: Vec<u8>,
+    next_hashed: &'a [u8],
+    flags: u8,
+    iterations: u16,
+    salt: &'a [u8],
+    types: &'a [QueryType],
+}
+
+// NSEC3 records we can hash for. Unknown hash algorithms are ignored (RFC 5155 section 8.1),
+// None when all the others ask for too much work, the zone is then taken as insecure
+fn nsec3s(records: &[DnsRecord]) -> Option<Vec<Nsec3<'_>>> {
+    let mut nsec3s = Vec::new();
+    let mut too_many_iterations = false;
+    for record in records {
+        let DnsRecord::NSEC3 {
+            domain,
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hashed,
+            types,
+            ..
+        } = record
+        else {
+            continue;
+        };
+        if *hash_algorithm != NSEC3_SHA1 {
+            continue;
+        }
+        if *iterations > MAX_NSEC3_ITERATIONS {
+            too_many_iterations = true;
+            continue;
+        }
+        let (Some(label), Some(zone)) = (domain.labels().first(), domain.parent()) else {
+            continue;
+        };
+        let Some(hashed_owner) = base32hex_decode(label) else {
+            continue;
+        };
+        nsec3s.push(Nsec3 {
+            zone,
+            hashed_owner,
+            next_hashed,
+            flags: *flags,
+            iterations: *iterations,
+            salt,
+            types,
+        });
+    }
+
+    if nsec3s.is_empty() && too_many_iterations {
+        return None;
+    }
+    Some(nsec3s)
+}
+
+// RFC 5155 section 5, SHA-1 of the canonical name and salt, then again of the hash and salt
+pub fn nsec3_hash(name: &Name, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hash = canonical_name(name);
+    for _ in 0..=iterations {
+        hash = digest::digest(
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            &[hash.as_slice(), salt].concat(),
+        )
+        .as_ref()
+        .to_vec();
+    }
+    hash
+}
+
+impl Nsec3<'_> {
+    fn hash(&self, name: &Name) -> Option<Vec<u8>> {
+        name.is_subdomain_of(&self.zone)
+            .then(|| nsec3_hash(name, self.salt, self.iterations))
+    }
+
+    fn matches(&self, name: &Name) -> bool {
+        self.hash(name)
+            .is_some_and(|hash| hash == self.hashed_owner)
+    }
+
+    fn covers(&self, name: &Name) -> bool {
+        let Some(hash) = self.hash(name) else {
+            return false;
+        };
+        let (owner, next) = (self.hashed_owner.as_slice(), self.next_hashed);
+        if owner < next {
+            owner < hash.as_slice() && hash.as_slice() < next
+        } else {
+            owner < hash.as_slice() || hash.as_slice() < next
+        }
+    }
+
+    fn is_opt_out(&self) -> bool {
+        self.flags & NSEC3_OPT_OUT != 0
+    }
+}
+
+// RFC 5155 section 8.3: the closest encloser has an NSEC3, the name one label below it
+// towards the qname (the next closer name) is covered by one. Returns both
+fn closest_encloser<'a>(nsec3s: &'a [Nsec3], qname: &Name) -> Option<(Name, &'a Nsec3<'a>)> {
+    (1..=qname.label_count()).find_map(|skip| {
+        let encloser = qname.suffix(skip);
+        if !nsec3s.iter().any(|nsec3| nsec3.matches(&encloser)) {
+            return None;
+        }
+        let next_closer = qname.suffix(skip - 1);
+        nsec3s
+            .iter()
+            .find(|nsec3| nsec3.covers(&next_closer))
+            .map(|nsec3| (encloser, nsec3))
+    })
+}
+
+// Whether the types of a name prove it has no records of the query type. At a delegation the
+// parent only speaks for the DS, and the child zone never does
+fn denies_type(types: &[QueryType], query_type: QueryType) -> bool {
+    let delegation = types.contains(&QueryType::NS) && !types.contains(&QueryType::SOA);
+    !types.contains(&query_type)
+        && !types.contains(&QueryType::CNAME)
+        && match query_type {
+            QueryType::DS => !types.contains(&QueryType::SOA) || delegation,
+            _ => !delegation,
+        }
+}
+
+fn bogus(reason: &str, qname: &Name) -> Security {
+    Security::Bogus(format!("{} for {}", reason, qname))
+}
+
+// Checks the NSEC or NSEC3 records of the authority section, already verified, prove that the
+// qname doesn't exist, or has no records of the query type
+pub fn prove_denial(
+    authorities: &[DnsRecord],
+    qname: &Name,
+    query_type: QueryType,
+    nxdomain: bool,
+) -> Security {
+    let nsecs = nsecs(authorities);
+    if !nsecs.is_empty() {
+        return prove_nsec_denial(&nsecs, qname, query_type, nxdomain);
+    }
+    match nsec3s(authorities) {
+        Some(nsec3s) if nsec3s.is_empty() => bogus("no NSEC or NSEC3 records", qname),
+        Some(nsec3s) => prove_nsec3_denial(&nsec3s, qname, query_type, nxdomain),
+        None => Security::Insecure,
+    }
+}
+
+fn prove_nsec_denial(
+    nsecs: &[Nsec],
+    qname: &Name,
+    query_type: QueryType,
+    nxdomain: bool,
+) -> Security {
+    if !nxdomain {
+        if let Some(nsec) = nsecs.iter().find(|nsec| nsec.owner == qname) {
+            return match denies_type(nsec.types, query_type) {
+                true => Security::Secure,
+                false => bogus("the NSEC doesn't deny the type", qname),
+            };
+        }
+        // An empty non-terminal, there are names under it but nothing of its own
+        if nsecs
+            .iter()
+            .any(|nsec| nsec.covers(qname) && nsec.next.is_subdomain_of(qname))
+        {
+            return Security::Secure;
+        }
+    }
+
+    let Some(covering) = nsecs.iter().find(|nsec| nsec.covers(qname)) else {
+        return bogus("no NSEC covers the name", qname);
+    };
+    // The closest encloser is the longest ancestor of the qname the NSEC shows to exist
+    let Some(wildcard) = (1..=qname.label_count())
+        .map(|skip| qname.suffix(skip))
+        .find(|name| covering.owner.is_subdomain_of(name) || covering.next.is_subdomain_of(name))
+        .and_then(|encloser| encloser.wildcard().ok())
+    else {
+        return bogus("no closest encloser", qname);
+    };
+
+    match nxdomain {
+        true if nsecs.iter().any(|nsec| nsec.covers(&wildcard)) => Security::Secure,
+        true => bogus("no NSEC denies the wildcard", qname),
+        // The answer would have come from a wildcard which doesn't have the type
+        false => match nsecs.iter().find(|nsec| *nsec.owner == wildcard) {
+            Some(nsec) if denies_type(nsec.types, query_type) => Security::Secure,
+            _ => bogus("the NSEC doesn't deny the type", qname),
+        },
+    }
+}
+
+fn prove_nsec3_denial(
+    nsec3s: &[Nsec3],
+    qname: &Name,
+    query_type: QueryType,
+    nxdomain: bool,
+) -> Security {
+    if !nxdomain {
+        if let Some(nsec3) = nsec3s.iter().find(|nsec3| nsec3.matches(qname)) {
+            return match denies_type(nsec3.types, query_type) {
+                true => Security::Secure,
+                false => bogus("the NSEC3 doesn't deny the type", qname),
+            };
+        }
+    }
+
+    let Some((encloser, next_closer)) = closest_encloser(nsec3s, qname) else {
+        return bogus("no NSEC3 closest encloser proof", qname);
+    };
+    let Ok(wildcard) = encloser.wildcard() else {
+        return bogus("no closest encloser", qname);
+    };
+
+    // Unsigned delegations may be left out of the chain, what's under them is insecure
+    let proven = match nxdomain {
+        true => nsec3s.iter().any(|nsec3| nsec3.covers(&wildcard)),
+        false => {
+            (query_type == QueryType::DS && next_closer.is_opt_out())
+                || nsec3s
+                    .iter()
+                    .any(|nsec3| nsec3.matches(&wildcard) && denies_type(nsec3.types, query_type))
+        }
+    };
+    match (proven, next_closer.is_opt_out()) {
+        (true, true) => Security::Insecure,
+        (true, false) => Security::Secure,
+        (false, _) => bogus("the NSEC3 records don't deny the name", qname),
+    }
+}
+
+// An answer expanded from a wildcard needs proof the qname itself doesn't exist
+pub fn prove_wildcard_answer(authorities: &[DnsRecord], qname: &Name, source: &Name) -> Security {
+    let nsecs = nsecs(authorities);
+    if !nsecs.is_empty() {
+        return match nsecs.iter().any(|nsec| nsec.covers(qname)) {
+            true => Security::Secure,
+            false => bogus("no NSEC proves the wildcard answer", qname),
+        };
+    }
+
+    let Some(nsec3s) = nsec3s(authorities) else {
+        return Security::Insecure;
+    };
+    let next_closer = qname.suffix(qname.label_count() - source.label_count() - 1);
+    match nsec3s.iter().find(|nsec3| nsec3.covers(&next_closer)) {
+        Some(nsec3) if nsec3.is_opt_out() => Security::Insecure,
+        Some(_) => Security::Secure,
+        None => bogus("no NSEC3 proves the wildcard answer", qname),
+    }
+}
+
+// Given a secure NODATA answer to a DS query, whether the name is a delegation to an unsigned
+// zone rather than a name without a zone of its own
+pub fn proves_insecure_delegation(authorities: &[DnsRecord], name: &Name) -> bool {
+    let is_unsigned_cut = |types: &[QueryType]| {
+        types.contains(&QueryType::NS)
+            && !types.contains(&QueryType::DS)
+            && !types.contains(&QueryType::SOA)
+    };
+
+    let nsecs = nsecs(authorities);
+    if !nsecs.is_empty() {
+        return nsecs
+            .iter()
+            .any(|nsec| nsec.owner == name && is_unsigned_cut(nsec.types));
+    }
+
+    let Some(nsec3s) = nsec3s(authorities) else {
+        return false;
+    };
+    match nsec3s.iter().find(|nsec3| nsec3.matches(name)) {
+        Some(nsec3) => is_unsigned_cut(nsec3.types),
+        None => {
+            closest_encloser(&nsec3s, name).is_some_and(|(_, next_closer)| next_closer.is_opt_out())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dns_packet::DnsPacket,
+        result_code::ResultCode,
+        test_zone::{self, name},
+        zone::Zone,
+    };
+
+    const DAY: u32 = 24 * 3600;
+
+    fn lookup(zone: &Zone, qname: &str, query_type: QueryType) -> DnsPacket {
+        zone.lookup(&name(qname), query_type, true)
+    }
+
+    // Every RRset of the records verifies with the keys at the given time
+    fn verify_all(records: &[DnsRecord], keys: &[DnsRecord], now: u32) -> Result<(), String> {
+        for rrset in rrsets(records) {
+            verify_rrset(&rrset, keys, now)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn secure_answers_verify() {
+        let zone = test_zone::zone(false);
+        let keys = test_zone::dnskeys(&zone);
+
+        // The key set is signed by the KSK, the key with the SEP bit the DS points at
+        let ksk = keys
+            .iter()
+            .find(|key| matches!(key, DnsRecord::DNSKEY { flags, .. } if flags & 0x0001 != 0))
+            .unwrap();
+        let ds = ds_of(ksk, SHA256).unwrap();
+        assert!(keys.iter().any(|key| ds_matches(&ds, key)));
+        assert_eq!(verify_all(&keys, &keys, now()), Ok(()));
+
+        let answer = lookup(&zone, "www.example.", QueryType::A);
+        assert_eq!(answer.answers.len(), 2);
+        assert_eq!(verify_all(&answer.answers, &keys, now()), Ok(()));
+    }
+
+    #[test]
+    fn tampered_signatures_are_bogus() {
+        let zone = test_zone::zone(false);
+        let keys = test_zone::dnskeys(&zone);
+
+        let mut answer = lookup(&zone, "www.example.", QueryType::A);
+        for record in &mut answer.answers {
+            if let DnsRecord::RRSIG { signature, .. } = record {
+                signature[0] ^= 0xff;
+            }
+        }
+
+        assert!(verify_all(&answer.answers, &keys, now())
+            .unwrap_err()
+            .contains("verifies"));
+    }
+
+    #[test]
+    fn signatures_are_valid_only_while_current() {
+        let zone = test_zone::zone(false);
+        let keys = test_zone::dnskeys(&zone);
+        let answer = lookup(&zone, "www.example.", QueryType::A);
+
+        for at in [now() + 8 * DAY, now() - DAY] {
+            assert!(verify_all(&answer.answers, &keys, at)
+                .unwrap_err()
+                .contains("expired"));
+        }
+    }
+
+    #[test]
+    fn records_of_another_zone_key_are_bogus() {
+        let zone = test_zone::zone(false);
+        let other_keys = test_zone::dnskeys(&test_zone::zone(false));
+        let answer = lookup(&zone, "www.example.", QueryType::A);
+
+        assert!(verify_all(&answer.answers, &other_keys, now()).is_err());
+    }
+
+    // NXDOMAIN and NODATA answers of the zone, proven by the verified NSEC or NSEC3 records
+    fn assert_denials(nsec3: bool) {
+        let zone = test_zone::zone(nsec3);
+        let keys = test_zone::dnskeys(&zone);
+
+        for (qname, query_type, rescode) in [
+            ("nx.example.", QueryType::A, ResultCode::NXDOMAIN),
+            ("x.nx.example.", QueryType::A, ResultCode::NXDOMAIN),
+            ("x.www.example.", QueryType::AAAA, ResultCode::NXDOMAIN),
+            ("www.example.", QueryType::MX, ResultCode::NOERROR),
+            ("example.", QueryType::A, ResultCode::NOERROR),
+            // Empty non-terminal
+            ("b.example.", QueryType::A, ResultCode::NOERROR),
+        ] {
+            let answer = lookup(&zone, qname, query_type);
+            assert_eq!(answer.header.rescode, rescode, "{}", qname);
+            assert!(answer.answers.is_empty(), "{}", qname);
+            assert_eq!(verify_all(&answer.authorities, &keys, now()), Ok(()));
+
+            let nxdomain = rescode == ResultCode::NXDOMAIN;
+            assert_eq!(
+                prove_denial(&answer.authorities, &name(qname), query_type, nxdomain),
+                Security::Secure,
+                "{} {}",
+                qname,
+                query_type
+            );
+        }
+
+        // The proof of one name says nothing about another
+        let answer = lookup(&zone, "nx.example.", QueryType::A);
+        assert!(matches!(
+            prove_denial(
+                &answer.authorities,
+                &name("www.example."),
+                QueryType::A,
+                true
+            ),
+            Security::Bogus(_)
+        ));
+        let answer = lookup(&zone, "www.example.", QueryType::MX);
+        assert!(matches!(
+            prove_denial(
+                &answer.authorities,
+                &name("www.example."),
+                QueryType::A,
+                false
+            ),
+            Security::Bogus(_)
+        ));
+
+        // The parent proves the delegation unsigned
+        let answer = lookup(&zone, "sub.example.", QueryType::DS);
+        assert_eq!(
+            prove_denial(
+                &answer.authorities,
+                &name("sub.example."),
+                QueryType::DS,
+                false
+            ),
+            Security::Secure
+        );
+        assert!(proves_insecure_delegation(
+            &answer.authorities,
+            &name("sub.example.")
+        ));
+    }
+
+    #[test]
+    fn nsec_denials_are_proven() {
+        assert_denials(false);
+    }
+
+    #[test]
+    fn nsec3_denials_are_proven() {
+        assert_denials(true);
+    }
+
+    // The NSEC3 records of an NXDOMAIN answer, with `change` applied to a copy of each of them
+    fn nsec3_proof(change: impl Fn(&mut DnsRecord)) -> (Vec<DnsRecord>, Vec<DnsRecord>) {
+        let zone = test_zone::zone(true);
+        let proof: Vec<DnsRecord> = lookup(&zone, "nx.example.", QueryType::A)
+            .authorities
+            .into_iter()
+            .filter(|record| matches!(record, DnsRecord::NSEC3 { .. }))
+            .collect();
+        let changed = proof
+            .iter()
+            .cloned()
+            .map(|mut record| {
+                change(&mut record);
+                record
+            })
+            .collect();
+
+        (proof, changed)
+    }
+
+    #[test]
+    fn nsec3_of_unknown_hash_algorithms_are_ignored() {
+        let (proof, unknown) = nsec3_proof(|record| {
+            if let DnsRecord::NSEC3 { hash_algorithm, .. } = record {
+                *hash_algorithm = 2;
+            }
+        });
+        let qname = name("nx.example.");
+
+        let mixed = [proof, unknown.clone()].concat();
+        assert_eq!(
+            prove_denial(&mixed, &qname, QueryType::A, true),
+            Security::Secure
+        );
+        assert!(matches!(
+            prove_denial(&unknown, &qname, QueryType::A, true),
+            Security::Bogus(_)
+        ));
+    }
+
+    #[test]
+    fn nsec3_over_the_iteration_limit_are_insecure_only_all_together() {
+        let (proof, costly) = nsec3_proof(|record| {
+            if let DnsRecord::NSEC3 { iterations, .. } = record {
+                *iterations = MAX_NSEC3_ITERATIONS + 1;
+            }
+        });
+        let qname = name("nx.example.");
+
+        let mixed = [proof, costly.clone()].concat();
+        assert_eq!(
+            prove_denial(&mixed, &qname, QueryType::A, true),
+            Security::Secure
+        );
+        assert_eq!(
+            prove_denial(&costly, &qname, QueryType::A, true),
+            Security::Insecure
+        );
+    }
+
+    #[test]
+    fn wildcard_expansions_are_proven() {
+        for nsec3 in [false, true] {
+            let zone = test_zone::zone(nsec3);
+            let keys = test_zone::dnskeys(&zone);
+            let qname = name("x.y.wild.example.");
+
+            let answer = lookup(&zone, "x.y.wild.example.", QueryType::A);
+            assert_eq!(answer.answers[0].domain(), &qname);
+            assert_eq!(verify_all(&answer.answers, &keys, now()), Ok(()));
+            assert_eq!(verify_all(&answer.authorities, &keys, now()), Ok(()));
+
+            let rrsets = rrsets(&answer.answers);
+            let rrsig = verify_rrset(&rrsets[0], &keys, now()).unwrap();
+            let source = wildcard_source(&qname, rrsig).unwrap();
+            assert_eq!(source, name("wild.example."));
+            assert_eq!(
+                prove_wildcard_answer(&answer.authorities, &qname, &source),
+                Security::Secure
+            );
+
+            // Without the proof the name might exist with other records
+            let unproven: Vec<DnsRecord> = answer
+                .authorities
+                .iter()
+                .filter(|record| {
+                    !matches!(record, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. })
+                })
+                .cloned()
+                .collect();
+            assert!(matches!(
+                prove_wildcard_answer(&unproven, &qname, &source),
+                Security::Bogus(_)
+            ));
+        }
+    }
+}
@@ -1,7 +1,21 @@
-use std::{error, fmt::Display, io, net::UdpSocket};
+use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
+pub type NameResult<T> = Result<T, NameError>;
+
+#[derive(Error, Debug)]
+pub enum NameError {
+    #[error("Label should be at most 63 bytes long. Len received - {length}")]
+    LabelTooLong { length: usize },
+    #[error("Name should be at most 255 bytes long. Len received - {length}")]
+    NameTooLong { length: usize },
+    #[error("Name {input} has an empty label")]
+    EmptyLabel { input: String },
+    #[error("Name {input} has an invalid escape")]
+    InvalidEscape { input: String },
+}
+
 #[derive(Error, Debug)]
 pub enum BytePacketBufferError {
     #[error("Position out of the buffer size")]
@@ -10,29 +24,58 @@ pub enum BytePacketBufferError {
     RangeOutOfTheBuffer,
     #[error("Maximum jumps exceeded, Limit is - {limit}")]
     MaxJumpsExceeded { limit: usize },
-    #[error("Label error should be smaller then 64 symbol length. Len received - {length} for the lable {label} in the input {input}")]
-    LabelLengthTooBig {
-        length: usize,
-        label: String,
-        input: String,
-    },
+    #[error("Invalid name - {error}")]
+    InvalidName { error: NameError },
+    #[error("Character string should be at most 255 bytes long. Len received - {length}")]
+    CharacterStringTooLong { length: usize },
+    #[error("Record data should be at most 65535 bytes long. Len received - {length}")]
+    RdataTooLong { length: usize },
+    #[error("Record data is {expected} bytes long, but its fields take {read}")]
+    RdataLengthMismatch { expected: usize, read: usize },
+}
+
+impl From<NameError> for BytePacketBufferError {
+    fn from(err: NameError) -> Self {
+        Self::InvalidName { error: err }
+    }
 }
 
 pub type DnsServerResult<T> = Result<T, DnsServerError>;
 
 #[derive(Error, Debug)]
 pub enum DnsServerError {
-    #[error("Udp Error occured - {error}")]
-    UdpError { error: io::Error },
+    #[error("Io Error occured - {error}")]
+    IoError { error: io::Error },
     #[error("Buffer error occured - {error}")]
     BytePacketBufferErr { error: BytePacketBufferError },
     #[error("Packet is corrupted. Sent id - {sent_id}, received id - {received_id}")]
     PacketIdCorrupted { sent_id: u16, received_id: u16 },
+    #[error("Answer doesn't match the question sent for {qname}")]
+    QuestionMismatch { qname: String },
+    #[error("Answer doesn't echo the letter case of the question sent for {qname}")]
+    CaseMismatch { qname: String },
+    #[error("None of the name servers answered the query for {qname}")]
+    NameServersExhausted { qname: String },
+    #[error("Priming response has no usable root name servers")]
+    PrimingFailed,
+    #[error("Resolving {qname} took more than {limit} lookups, giving up")]
+    TooManyLookups { qname: String, limit: usize },
+    #[error("DNSSEC validation of the answer for {qname} failed - {reason}")]
+    Bogus { qname: String, reason: String },
+}
+
+impl DnsServerError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Self::IoError { error } if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+        )
+    }
 }
 
 impl From<io::Error> for DnsServerError {
     fn from(err: io::Error) -> Self {
-        Self::UdpError { error: err }
+        Self::IoError { error: err }
     }
 }
 
@@ -41,3 +84,50 @@ impl From<BytePacketBufferError> for DnsServerError {
         Self::BytePacketBufferErr { error: err }
     }
 }
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Can't read config file {path:?} - {error}")]
+    Read { path: PathBuf, error: io::Error },
+    #[error("Config file {path:?} is invalid - {error}")]
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("Flag {flag} expects a value")]
+    MissingValue { flag: String },
+    #[error("Invalid value {value} for {flag}")]
+    InvalidValue { flag: String, value: String },
+    #[error("Can't load root hints - {error}")]
+    RootHints { error: ZoneError },
+    #[error("Invalid configuration - {reason}")]
+    Invalid { reason: String },
+    #[error("Unknown flag {flag}\n\n{usage}")]
+    UnknownFlag { flag: String, usage: &'static str },
+}
+
+pub type BlocklistResult<T> = Result<T, BlocklistError>;
+
+#[derive(Error, Debug)]
+pub enum BlocklistError {
+    #[error("Can't read blocklist {path:?} - {error}")]
+    Read { path: PathBuf, error: io::Error },
+}
+
+pub type ZoneResult<T> = Result<T, ZoneError>;
+
+#[derive(Error, Debug)]
+pub enum ZoneError {
+    #[error("Can't read zone file {path:?} - {error}")]
+    Read { path: PathBuf, error: io::Error },
+    #[error("Zone file {path:?} line {line} - {reason}")]
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    #[error("Zone {origin} is invalid - {reason}")]
+    Invalid { origin: String, reason: String },
+}
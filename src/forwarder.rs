@@ -0,0 +1,95 @@
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{ForwardConfig, ResolverMode},
+    name::Name,
+    server_stats::ServerStats,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    RoundRobin,
+    Fastest,
+}
+
+pub struct UpstreamGroup {
+    servers: Vec<SocketAddr>,
+    strategy: SelectionStrategy,
+    next: AtomicUsize,
+}
+
+impl UpstreamGroup {
+    pub fn new(servers: Vec<SocketAddr>, strategy: SelectionStrategy) -> Self {
+        Self {
+            servers,
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Order in which the servers are asked, the ones after the first are the failover
+    pub fn ordered(&self, stats: &ServerStats) -> Vec<SocketAddr> {
+        let mut servers = self.servers.clone();
+
+        match self.strategy {
+            SelectionStrategy::RoundRobin if !servers.is_empty() => {
+                let first = self.next.fetch_add(1, Ordering::Relaxed) % servers.len();
+                servers.rotate_left(first);
+            }
+            SelectionStrategy::RoundRobin => {}
+            SelectionStrategy::Fastest => stats.sort_fastest(&mut servers),
+        }
+
+        servers
+    }
+}
+
+// Decides which queries are sent to upstream resolvers instead of being resolved from the root
+pub struct Forwarder {
+    // (domain, upstreams), longer domains are more specific and win
+    rules: Vec<(Name, UpstreamGroup)>,
+    default: Option<UpstreamGroup>,
+}
+
+impl Forwarder {
+    pub fn from_config(mode: ResolverMode, config: &ForwardConfig) -> Self {
+        let mut rules: Vec<(Name, UpstreamGroup)> = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let strategy = rule.strategy.unwrap_or(config.strategy);
+
+                (
+                    rule.domain.clone(),
+                    UpstreamGroup::new(rule.upstreams.clone(), strategy),
+                )
+            })
+            .collect();
+        rules.sort_by_key(|(domain, _)| std::cmp::Reverse(domain.label_count()));
+
+        let default = match mode {
+            ResolverMode::Forward => Some(UpstreamGroup::new(
+                config.upstreams.clone(),
+                config.strategy,
+            )),
+            ResolverMode::Recursive => None,
+        };
+
+        Self { rules, default }
+    }
+
+    // None means the name has to be resolved recursively
+    pub fn upstreams_for(&self, qname: &Name) -> Option<&UpstreamGroup> {
+        self.rules
+            .iter()
+            .find(|(domain, _)| qname.is_subdomain_of(domain))
+            .map(|(_, group)| group)
+            .or(self.default.as_ref())
+    }
+}
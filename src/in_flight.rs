@@ -0,0 +1,62 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+// Counts recursions or tcp connections running at the same time and refuses new ones above the limit
+pub struct InFlightLimiter {
+    limit: usize,
+    current: AtomicUsize,
+}
+
+pub struct InFlightPermit<'a> {
+    limiter: &'a InFlightLimiter,
+}
+
+// Same as InFlightPermit, for permits that move to another thread
+pub struct OwnedInFlightPermit {
+    limiter: Arc<InFlightLimiter>,
+}
+
+impl InFlightLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<InFlightPermit<'_>> {
+        self.increment().then_some(InFlightPermit { limiter: self })
+    }
+
+    pub fn try_acquire_owned(self: &Arc<Self>) -> Option<OwnedInFlightPermit> {
+        self.increment().then(|| OwnedInFlightPermit {
+            limiter: Arc::clone(self),
+        })
+    }
+
+    fn increment(&self) -> bool {
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < self.limit).then_some(current + 1)
+            })
+            .is_ok()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for InFlightPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Drop for OwnedInFlightPermit {
+    fn drop(&mut self) {
+        self.limiter.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
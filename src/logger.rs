@@ -0,0 +1,32 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Errors and warnings go to stderr, everything else to stdout
+struct ConsoleLogger;
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("[{}] {}", record.level(), record.args()),
+            _ => println!("[{}] {}", record.level(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(level: LevelFilter) {
+    // Fails only if a logger is already set, which means init was called twice
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
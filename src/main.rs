@@ -1,24 +1,236 @@
-use std::net::UdpSocket;
+#![allow(clippy::upper_case_acronyms)]
 
+use std::{
+    net::{SocketAddr, TcpListener},
+    process,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use blocklist::Blocklist;
+use config::{Config, ResolverMode};
 use dns_server::DnsServer;
+use dnssec::TrustAnchors;
+use in_flight::InFlightLimiter;
+use query_log::QueryLog;
+use zone::Zones;
+mod blocklist;
 mod byte_packet_buffer;
+mod chaos;
+mod config;
+mod dns_cache;
 mod dns_header;
+mod dns_listener;
 mod dns_packet;
 mod dns_question;
 mod dns_records;
 mod dns_server;
+mod dnssec;
 mod errors;
+mod forwarder;
+mod in_flight;
+mod logger;
+mod metrics;
+mod name;
+mod query_class;
+mod query_log;
 mod query_type;
 mod result_code;
+mod root_hints;
+mod server_stats;
+mod signer;
+#[cfg(test)]
+mod test_zone;
+mod zone;
+mod zone_file;
+
+// One thread per connection, up to max_connections of them. Idle clients hold their thread
+// until the idle timeout, so connections above the limit are closed right away
+fn spawn_tcp_listener(
+    dns_server: &Arc<DnsServer>,
+    addr: SocketAddr,
+    max_connections: usize,
+) -> JoinHandle<()> {
+    let listener = dns_listener::bind_tcp(addr)
+        .unwrap_or_else(|e| panic!("Can't listen on tcp {}: {}", addr, e));
+    let tcp_server = Arc::clone(dns_server);
+    let connections = Arc::new(InFlightLimiter::new(max_connections));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("Failed to accept tcp connection: {}", e);
+                    continue;
+                }
+            };
+
+            let Some(permit) = connections.try_acquire_owned() else {
+                log::warn!("Too many tcp connections on {}, closing a new one", addr);
+                continue;
+            };
+
+            let server = Arc::clone(&tcp_server);
+            thread::spawn(move || {
+                if let Err(e) = dns_listener::handle_tcp_connection(&server, stream) {
+                    log::error!("An error occurred: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    })
+}
+
+// Each scrape on a thread of its own, so a client which never sends its request holds up only
+// itself. A handful are plenty for scrapers, more are closed right away
+fn spawn_metrics_listener(dns_server: &Arc<DnsServer>, addr: SocketAddr) -> JoinHandle<()> {
+    let listener = TcpListener::bind(addr)
+        .unwrap_or_else(|e| panic!("Can't listen for metrics on {}: {}", addr, e));
+    let metrics_server = Arc::clone(dns_server);
+    let connections = Arc::new(InFlightLimiter::new(metrics::MAX_HTTP_CONNECTIONS));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Metrics request failed: {}", e);
+                    continue;
+                }
+            };
+
+            let Some(permit) = connections.try_acquire_owned() else {
+                log::warn!(
+                    "Too many metrics connections on {}, closing a new one",
+                    addr
+                );
+                continue;
+            };
+
+            let server = Arc::clone(&metrics_server);
+            thread::spawn(move || {
+                if let Err(e) = metrics::handle_http_connection(&server, stream) {
+                    log::warn!("Metrics request failed: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    })
+}
+
+// Every worker waits on its own handle of the same socket, so a slow recursion blocks only one of them
+fn spawn_udp_workers(
+    dns_server: &Arc<DnsServer>,
+    addr: SocketAddr,
+    workers: usize,
+) -> Vec<JoinHandle<()>> {
+    let socket = dns_listener::bind_udp(addr)
+        .unwrap_or_else(|e| panic!("Can't listen on udp {}: {}", addr, e));
+
+    (0..workers)
+        .map(|_| {
+            let server = Arc::clone(dns_server);
+            let socket = socket.try_clone().unwrap();
+
+            thread::spawn(move || loop {
+                match dns_listener::handle_udp_query(&server, &socket) {
+                    Ok(_) => {}
+                    Err(e) => log::error!("An error occurred: {}", e),
+                }
+            })
+        })
+        .collect()
+}
 
 fn main() {
-    let mut dns_server = DnsServer::default();
-    let socket = UdpSocket::bind(("0.0.0.0", 2053)).unwrap();
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    loop {
-        match dns_server.handle_query(&socket) {
-            Ok(_) => {}
-            Err(e) => eprintln!("An error occurred: {}", e),
+    logger::init(config.log_level);
+
+    if let Some(request) = &config.generate_key {
+        match signer::generate_key(&request.zone, request.ksk, request.algorithm) {
+            Ok(key) => print!("{}", key),
+            Err(e) => {
+                log::error!("{}", e);
+                process::exit(1);
+            }
         }
+        return;
+    }
+
+    let zones = match Zones::load(&config.zones) {
+        Ok(zones) => zones,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut builder = DnsServer::builder(&config).zones(zones);
+    if config.dnssec.validate {
+        match TrustAnchors::load(&config.dnssec) {
+            Ok(anchors) => builder = builder.trust_anchors(anchors),
+            Err(e) => {
+                log::error!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if !config.blocklist.lists.is_empty() {
+        match Blocklist::load(&config.blocklist) {
+            Ok(blocklist) => builder = builder.blocklist(blocklist),
+            Err(e) => {
+                log::error!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    match QueryLog::open(&config.query_log) {
+        Ok(Some(query_log)) => builder = builder.query_log(query_log),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Can't open the query log - {}", e);
+            process::exit(1);
+        }
+    }
+    let dns_server = Arc::new(builder.build());
+
+    // Queries are served from the hints until the priming is done
+    if config.mode == ResolverMode::Recursive && config.recursion {
+        let server = Arc::clone(&dns_server);
+        thread::spawn(move || server.prime());
+    }
+
+    {
+        let server = Arc::clone(&dns_server);
+        thread::spawn(move || server.watch_blocklist());
+    }
+
+    let mut handles = Vec::new();
+    for addr in &config.listen {
+        log::info!("Listening on {}", addr);
+
+        handles.push(spawn_tcp_listener(
+            &dns_server,
+            *addr,
+            config.max_tcp_connections.max(1),
+        ));
+        handles.extend(spawn_udp_workers(&dns_server, *addr, config.workers.max(1)));
+    }
+
+    if let Some(addr) = config.metrics_listen {
+        log::info!("Serving metrics on http://{}/metrics", addr);
+        handles.push(spawn_metrics_listener(&dns_server, addr));
+    }
+
+    for handle in handles {
+        let _ = handle.join();
     }
 }
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{dns_server::DnsServer, query_type::QueryType, result_code::ResultCode};
+
+// Upper bounds of the latency buckets in seconds, the same as the Prometheus client defaults
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// Scrapers send a short GET, anything longer isn't one
+const MAX_REQUEST_SIZE: usize = 8192;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// Scrapes served at the same time
+pub const MAX_HTTP_CONNECTIONS: usize = 16;
+
+// Gauges are read from the server when scraped, these are what it can't tell by itself
+#[derive(Default)]
+pub struct Metrics {
+    // By type and rcode, types we don't know are counted together so clients can't blow up the labels
+    queries: Mutex<HashMap<(Option<QueryType>, ResultCode), u64>>,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_us: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    upstream_timeouts: AtomicU64,
+    packet_id_corrupted: AtomicU64,
+}
+
+// What the server has at the moment of the scrape
+pub struct Gauges {
+    pub cache_entries: usize,
+    pub negative_cache_entries: usize,
+    pub in_flight: usize,
+}
+
+impl Metrics {
+    pub fn record_query(
+        &self,
+        query_type: Option<QueryType>,
+        rcode: ResultCode,
+        latency: Duration,
+    ) {
+        let query_type =
+            query_type.filter(|query_type| !matches!(query_type, QueryType::UNKNOWN(_)));
+        *self
+            .queries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((query_type, rcode))
+            .or_default() += 1;
+
+        let secs = latency.as_secs_f64();
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_timeout(&self) {
+        self.upstream_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_packet_id_corrupted(&self) {
+        self.packet_id_corrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prometheus text exposition format 0.0.4
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "swdns_queries_total",
+            "counter",
+            "Answered queries by type and rcode",
+        );
+        let mut queries: Vec<(String, String, u64)> = self
+            .queries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|((query_type, rcode), count)| {
+                let query_type = query_type.map_or("other".to_string(), |t| t.to_string());
+                (query_type, format!("{:?}", rcode), *count)
+            })
+            .collect();
+        queries.sort();
+        for (query_type, rcode, count) in queries {
+            let _ = writeln!(
+                out,
+                "swdns_queries_total{{qtype=\"{}\",rcode=\"{}\"}} {}",
+                query_type, rcode, count
+            );
+        }
+
+        header(
+            &mut out,
+            "swdns_query_duration_seconds",
+            "histogram",
+            "Time from receiving a query to having its answer",
+        );
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "swdns_query_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "swdns_query_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "swdns_query_duration_seconds_sum {}",
+            self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "swdns_query_duration_seconds_count {}", count);
+
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        header(
+            &mut out,
+            "swdns_cache_lookups_total",
+            "counter",
+            "Client queries of the recursion and forwarding by whether the cache answered them",
+        );
+        let _ = writeln!(out, "swdns_cache_lookups_total{{result=\"hit\"}} {}", hits);
+        let _ = writeln!(
+            out,
+            "swdns_cache_lookups_total{{result=\"miss\"}} {}",
+            misses
+        );
+
+        header(
+            &mut out,
+            "swdns_cache_hit_ratio",
+            "gauge",
+            "Share of those client queries the cache answered since the start",
+        );
+        let ratio = if hits + misses == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        };
+        let _ = writeln!(out, "swdns_cache_hit_ratio {}", ratio);
+
+        header(
+            &mut out,
+            "swdns_cache_entries",
+            "gauge",
+            "Record sets and negative answers in the cache",
+        );
+        let _ = writeln!(
+            out,
+            "swdns_cache_entries{{cache=\"positive\"}} {}",
+            gauges.cache_entries
+        );
+        let _ = writeln!(
+            out,
+            "swdns_cache_entries{{cache=\"negative\"}} {}",
+            gauges.negative_cache_entries
+        );
+
+        header(
+            &mut out,
+            "swdns_upstream_timeouts_total",
+            "counter",
+            "Queries to name servers and upstreams that weren't answered in time",
+        );
+        let _ = writeln!(
+            out,
+            "swdns_upstream_timeouts_total {}",
+            self.upstream_timeouts.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "swdns_packet_id_corrupted_total",
+            "counter",
+            "Upstream answers with another id than the query had",
+        );
+        let _ = writeln!(
+            out,
+            "swdns_packet_id_corrupted_total {}",
+            self.packet_id_corrupted.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "swdns_recursions_in_flight",
+            "gauge",
+            "Recursions running right now",
+        );
+        let _ = writeln!(out, "swdns_recursions_in_flight {}", gauges.in_flight);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+// Just enough HTTP/1.x for a scraper: GET /metrics, one request per connection
+pub fn handle_http_connection(server: &DnsServer, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = stream.read(&mut buf)?;
+        if len == 0 || request.len() + len > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", server.render_metrics()),
+        (Some("GET"), _) => ("404 Not Found", "Not found, try /metrics\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
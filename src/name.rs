@@ -0,0 +1,289 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use rand::Rng;
+
+use crate::errors::{NameError, NameResult};
+
+pub const MAX_LABEL_LENGTH: usize = 63;
+// In wire form, length bytes and the root label included
+pub const MAX_NAME_LENGTH: usize = 255;
+
+// Domain name as the labels seen on the wire. The case is kept as received, but names compare
+// case-insensitively (RFC 4343) and order canonically (RFC 4034 section 6.1)
+#[derive(Clone, Default)]
+pub struct Name {
+    // The leftmost label first, the root label is implied
+    labels: Vec<Vec<u8>>,
+}
+
+// For the places which need a reference to the root, e.g. the owner of an OPT record
+pub static ROOT: Name = Name { labels: Vec::new() };
+
+impl Name {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn from_labels(labels: Vec<Vec<u8>>) -> NameResult<Self> {
+        for label in &labels {
+            if label.is_empty() {
+                return Err(NameError::EmptyLabel {
+                    input: String::from_utf8_lossy(&labels.join(&b'.')).into_owned(),
+                });
+            }
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(NameError::LabelTooLong {
+                    length: label.len(),
+                });
+            }
+        }
+
+        let name = Self { labels };
+        if name.wire_len() > MAX_NAME_LENGTH {
+            return Err(NameError::NameTooLong {
+                length: name.wire_len(),
+            });
+        }
+
+        Ok(name)
+    }
+
+    // Presentation format with \X and \DDD escapes. Also tells whether the text was fully
+    // qualified, i.e. ended with an unescaped dot
+    pub fn parse_relative(text: &str) -> NameResult<(Self, bool)> {
+        if text == "." {
+            return Ok((Self::root(), true));
+        }
+
+        let invalid_escape = || NameError::InvalidEscape {
+            input: text.to_string(),
+        };
+
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut absolute = false;
+        let mut bytes = text.bytes();
+
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'.' if label.is_empty() => {
+                    return Err(NameError::EmptyLabel {
+                        input: text.to_string(),
+                    })
+                }
+                b'.' => {
+                    labels.push(std::mem::take(&mut label));
+                    absolute = bytes.len() == 0;
+                }
+                b'\\' => match bytes.next() {
+                    Some(digit) if digit.is_ascii_digit() => {
+                        let digits = [Some(digit), bytes.next(), bytes.next()];
+                        let value = digits
+                            .into_iter()
+                            .try_fold(0u16, |value, digit| match digit {
+                                Some(digit) if digit.is_ascii_digit() => {
+                                    Some(value * 10 + (digit - b'0') as u16)
+                                }
+                                _ => None,
+                            })
+                            .and_then(|value| u8::try_from(value).ok())
+                            .ok_or_else(invalid_escape)?;
+                        label.push(value);
+                    }
+                    Some(escaped) => label.push(escaped),
+                    None => return Err(invalid_escape()),
+                },
+                _ => label.push(byte),
+            }
+        }
+
+        if !label.is_empty() {
+            labels.push(label);
+        }
+
+        Ok((Self::from_labels(labels)?, absolute))
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn wire_len(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
+
+    // The name without its first `skip` labels, e.g. suffix(1) of www.example.com is example.com
+    pub fn suffix(&self, skip: usize) -> Self {
+        Self {
+            labels: self.labels[skip.min(self.labels.len())..].to_vec(),
+        }
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+
+        Some(self.suffix(1))
+    }
+
+    // True for the name itself too, labels are compared whole so notexample.com isn't under example.com
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self.suffix(self.labels.len() - other.labels.len()) == *other
+    }
+
+    // *.name, the wildcard which may stand in for the names under this one
+    pub fn wildcard(&self) -> NameResult<Self> {
+        Self::from_labels([vec![b"*".to_vec()], self.labels.clone()].concat())
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.labels.first().is_some_and(|label| label == b"*")
+    }
+
+    // `self` is relative to `origin`, e.g. www + example.com
+    pub fn append(&self, origin: &Name) -> NameResult<Self> {
+        Self::from_labels([self.labels.clone(), origin.labels.clone()].concat())
+    }
+
+    // Byte for byte, unlike ==
+    pub fn eq_case_sensitive(&self, other: &Name) -> bool {
+        self.labels == other.labels
+    }
+
+    // Every letter flips to upper or lower case at random (draft-vixie-dnsext-dns0x20), digits
+    // and other bytes stay as they are
+    pub fn randomize_case<R: Rng>(&self, rng: &mut R) -> Self {
+        Self {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| {
+                    label
+                        .iter()
+                        .map(|byte| match rng.gen::<bool>() {
+                            true => byte.to_ascii_uppercase(),
+                            false => byte.to_ascii_lowercase(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_lowercase(&self) -> Self {
+        Self {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Name {
+    type Err = NameError;
+
+    // Names given without the trailing dot are taken as fully qualified anyway
+    fn from_str(text: &str) -> NameResult<Self> {
+        Self::parse_relative(text).map(|(name, _)| name)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+        state.write_usize(self.labels.len());
+    }
+}
+
+impl Ord for Name {
+    // Labels are compared from the rightmost one, each as lowercase bytes
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.labels.iter().rev().zip(other.labels.iter().rev()) {
+            let ordering = a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Fully qualified, the root is just a dot. Bytes with a special meaning in zone files are
+// escaped, anything unprintable is written as \DDD
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+
+        for label in &self.labels {
+            for byte in label {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", *byte as char)?
+                    }
+                    0x21..=0x7e => write!(f, "{}", *byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+            write!(f, ".")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Copy, PartialOrd, Ord)]
+pub enum QueryClass {
+    UNKNOWN(u16),
+    IN,   // 1
+    CH,   // 3
+    HS,   // 4
+    NONE, // 254, dynamic update only
+    ANY,  // 255
+}
+
+impl From<QueryClass> for u16 {
+    fn from(value: QueryClass) -> Self {
+        match value {
+            QueryClass::UNKNOWN(x) => x,
+            QueryClass::IN => 1,
+            QueryClass::CH => 3,
+            QueryClass::HS => 4,
+            QueryClass::NONE => 254,
+            QueryClass::ANY => 255,
+        }
+    }
+}
+
+impl From<u16> for QueryClass {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => QueryClass::IN,
+            3 => QueryClass::CH,
+            4 => QueryClass::HS,
+            254 => QueryClass::NONE,
+            255 => QueryClass::ANY,
+            _ => QueryClass::UNKNOWN(value),
+        }
+    }
+}
+
+impl QueryClass {
+    // Mnemonic used in zone files, or the generic CLASSnnn from RFC 3597
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_uppercase();
+        if let Some(Ok(value)) = name.strip_prefix("CLASS").map(str::parse::<u16>) {
+            return Some(QueryClass::from(value));
+        }
+
+        match name.as_str() {
+            "IN" => Some(QueryClass::IN),
+            "CH" => Some(QueryClass::CH),
+            "HS" => Some(QueryClass::HS),
+            "NONE" => Some(QueryClass::NONE),
+            "ANY" => Some(QueryClass::ANY),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for QueryClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryClass::UNKNOWN(value) => write!(f, "CLASS{}", value),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::QueryLogConfig, dns_question::DnsQuestion, dns_records::civil_from_days,
+    result_code::ResultCode,
+};
+
+// Entries waiting for the writer, more than that are dropped instead of holding up the workers
+const QUEUE_SIZE: usize = 4096;
+// A lost socket or file is opened again at most that often
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Frame Streams, the transport of dnstap (https://github.com/farsightsec/fstrm)
+const DNSTAP_CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+const FSTRM_ACCEPT: u32 = 0x01;
+const FSTRM_START: u32 = 0x02;
+const FSTRM_READY: u32 = 0x04;
+const FSTRM_CONTENT_TYPE: u32 = 0x01;
+const MAX_CONTROL_FRAME: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryLogFormat {
+    // One human readable line per query
+    #[default]
+    Text,
+    // JSON lines
+    Json,
+    // Frame Streams of dnstap protobuf messages
+    Dnstap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+// What happened while a query was answered, collected on the thread answering it
+#[derive(Default)]
+pub struct Trace {
+    pub upstreams: Vec<SocketAddr>,
+    // None when the answer didn't involve the cache at all, e.g. it came from a zone
+    pub cache_hit: Option<bool>,
+}
+
+// cache=hit|miss|- upstreams=<addr>,...|-
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cache = match self.cache_hit {
+            Some(true) => "hit",
+            Some(false) => "miss",
+            None => "-",
+        };
+        write!(f, "cache={} upstreams=", cache)?;
+
+        if self.upstreams.is_empty() {
+            return write!(f, "-");
+        }
+        for (i, server) in self.upstreams.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { "," }, server)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static TRACE: RefCell<Option<Trace>> = const { RefCell::new(None) };
+}
+
+// Starts collecting for the query this thread is about to answer. Nothing is collected on threads
+// which didn't call it, like the one priming the root
+pub fn begin() {
+    TRACE.with(|trace| *trace.borrow_mut() = Some(Trace::default()));
+}
+
+pub fn finish() -> Trace {
+    TRACE.with(|trace| trace.borrow_mut().take().unwrap_or_default())
+}
+
+pub fn is_tracing() -> bool {
+    TRACE.with(|trace| trace.borrow().is_some())
+}
+
+pub fn cache_hit() -> Option<bool> {
+    TRACE.with(|trace| trace.borrow().as_ref().and_then(|trace| trace.cache_hit))
+}
+
+fn with_trace(f: impl FnOnce(&mut Trace)) {
+    TRACE.with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            f(trace);
+        }
+    });
+}
+
+pub fn record_upstream(server: SocketAddr) {
+    with_trace(|trace| {
+        if !trace.upstreams.contains(&server) {
+            trace.upstreams.push(server);
+        }
+    });
+}
+
+pub fn record_cache_hit(hit: bool) {
+    with_trace(|trace| trace.cache_hit = Some(hit));
+}
+
+// The recursion answered, from the cache unless it had to ask somebody
+pub fn record_resolved() {
+    with_trace(|trace| trace.cache_hit = Some(trace.upstreams.is_empty()));
+}
+
+// One answered query as the listener saw it
+pub struct LoggedQuery<'a> {
+    pub received: SystemTime,
+    pub answered: SystemTime,
+    pub client: SocketAddr,
+    pub transport: Transport,
+    pub question: Option<&'a DnsQuestion>,
+    pub rcode: ResultCode,
+    pub authoritative: bool,
+    // Both messages in wire format, dnstap carries them as they are
+    pub query: &'a [u8],
+    pub response: &'a [u8],
+    pub trace: Trace,
+}
+
+impl LoggedQuery<'_> {
+    fn latency(&self) -> Duration {
+        self.answered
+            .duration_since(self.received)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    client: SocketAddr,
+    protocol: Transport,
+    qname: Option<String>,
+    qtype: Option<String>,
+    rcode: String,
+    latency_ms: f64,
+    cache_hit: Option<bool>,
+    upstreams: &'a [SocketAddr],
+}
+
+#[derive(Clone)]
+enum Destination {
+    Stdout,
+    File(PathBuf),
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Destination {
+    fn from_config(config: &QueryLogConfig) -> Option<Self> {
+        if let Some(socket) = &config.socket {
+            return Some(match socket.parse() {
+                Ok(addr) => Destination::Tcp(addr),
+                Err(_) => Destination::Unix(PathBuf::from(socket)),
+            });
+        }
+
+        config.file.as_ref().map(|file| match file.to_str() {
+            Some("-") => Destination::Stdout,
+            _ => Destination::File(file.clone()),
+        })
+    }
+
+    fn is_socket(&self) -> bool {
+        matches!(self, Destination::Tcp(_) | Destination::Unix(_))
+    }
+}
+
+enum Stream {
+    Stdout(io::Stdout),
+    File(File),
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Stdout(out) => out.write(buf),
+            Stream::File(file) => file.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Stdout(out) => out.flush(),
+            Stream::File(file) => file.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+            _ => Ok(0),
+        }
+    }
+}
+
+fn open(destination: &Destination, format: QueryLogFormat) -> io::Result<Stream> {
+    let mut stream = match destination {
+        Destination::Stdout => Stream::Stdout(io::stdout()),
+        // A dnstap file is a single stream, it can't be appended to. Each one goes to a new file
+        // instead, the earlier ones are kept
+        Destination::File(path) if format == QueryLogFormat::Dnstap => {
+            let path = timestamped(path, SystemTime::now());
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            log::info!("Writing dnstap to {}", path.display());
+            Stream::File(file)
+        }
+        Destination::File(path) => {
+            Stream::File(OpenOptions::new().create(true).append(true).open(path)?)
+        }
+        // A collector which doesn't answer the handshake must not hang the writer
+        Destination::Tcp(addr) => {
+            let stream = TcpStream::connect_timeout(addr, RECONNECT_INTERVAL)?;
+            stream.set_read_timeout(Some(RECONNECT_INTERVAL))?;
+            Stream::Tcp(stream)
+        }
+        Destination::Unix(path) => {
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(RECONNECT_INTERVAL))?;
+            Stream::Unix(stream)
+        }
+    };
+
+    if format == QueryLogFormat::Dnstap {
+        // Collectors on a socket agree to the content type first, files just start
+        if destination.is_socket() {
+            stream.write_all(&control_frame(FSTRM_READY))?;
+            read_accept(&mut stream)?;
+        }
+        stream.write_all(&control_frame(FSTRM_START))?;
+        stream.flush()?;
+    }
+
+    Ok(stream)
+}
+
+// queries.dnstap becomes queries.20240101T120000Z.dnstap
+fn timestamped(path: &Path, time: SystemTime) -> PathBuf {
+    let stamp: String = format_time(time)[..19]
+        .chars()
+        .filter(|c| *c != '-' && *c != ':')
+        .collect();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}Z.{}", stem, stamp, extension.to_string_lossy()),
+        None => format!("{}.{}Z", stem, stamp),
+    };
+    path.with_file_name(name)
+}
+
+// Writes the entries on its own thread, so a slow disk or collector never holds up an answer
+pub struct QueryLog {
+    format: QueryLogFormat,
+    sender: SyncSender<Vec<u8>>,
+    dropped: AtomicU64,
+}
+
+impl QueryLog {
+    // None when no destination is configured
+    pub fn open(config: &QueryLogConfig) -> io::Result<Option<Self>> {
+        let Some(destination) = Destination::from_config(config) else {
+            return Ok(None);
+        };
+        let stream = open(&destination, config.format)?;
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let format = config.format;
+        thread::spawn(move || write_entries(receiver, destination, format, stream));
+
+        Ok(Some(Self {
+            format: config.format,
+            sender,
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn log(&self, query: &LoggedQuery) {
+        let entry = match self.format {
+            QueryLogFormat::Text => format!("{}\n", text_line(query)).into_bytes(),
+            QueryLogFormat::Json => json_line(query),
+            QueryLogFormat::Dnstap => data_frame(&dnstap_message(query)),
+        };
+
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(entry) {
+            // Warned about once per thousand, a flood of warnings wouldn't help the writer
+            if self
+                .dropped
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(1000)
+            {
+                log::warn!("query log can't keep up, dropping entries");
+            }
+        }
+    }
+}
+
+fn write_entries(
+    receiver: Receiver<Vec<u8>>,
+    destination: Destination,
+    format: QueryLogFormat,
+    stream: Stream,
+) {
+    let mut stream = Some(stream);
+    let mut last_attempt = None;
+
+    for entry in receiver {
+        if stream.is_none() {
+            let due = last_attempt.is_none_or(|at: Instant| at.elapsed() >= RECONNECT_INTERVAL);
+            if due {
+                last_attempt = Some(Instant::now());
+                stream = open(&destination, format)
+                    .inspect_err(|e| log::debug!("query log reopen failed - {}", e))
+                    .ok();
+            }
+        }
+        let Some(out) = stream.as_mut() else {
+            continue;
+        };
+
+        if let Err(e) = out.write_all(&entry).and_then(|_| out.flush()) {
+            log::warn!("Can't write the query log - {}", e);
+            stream = None;
+        }
+    }
+}
+
+fn text_line(query: &LoggedQuery) -> String {
+    let (qname, qtype) = match query.question {
+        Some(question) => (question.name.to_string(), question.query_type.to_string()),
+        None => ("-".to_string(), "-".to_string()),
+    };
+
+    format!(
+        "{} {} {} {} {} {:?} {:.1}ms {}",
+        format_time(query.received),
+        query.client,
+        if query.transport == Transport::Udp {
+            "udp"
+        } else {
+            "tcp"
+        },
+        qname,
+        qtype,
+        query.rcode,
+        query.latency().as_secs_f64() * 1000.0,
+        query.trace
+    )
+}
+
+fn json_line(query: &LoggedQuery) -> Vec<u8> {
+    let entry = JsonEntry {
+        time: format_time(query.received),
+        client: query.client,
+        protocol: query.transport,
+        qname: query.question.map(|question| question.name.to_string()),
+        qtype: query
+            .question
+            .map(|question| question.query_type.to_string()),
+        rcode: format!("{:?}", query.rcode),
+        // Down to microseconds, more digits are just noise
+        latency_ms: query.latency().as_micros() as f64 / 1000.0,
+        cache_hit: query.trace.cache_hit,
+        upstreams: &query.trace.upstreams,
+    };
+
+    let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+// RFC 3339 in UTC with milliseconds, e.g. 2024-01-01T12:00:00.000Z
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, day_secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Protobuf encoding of the few dnstap fields we fill, see dnstap.proto
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3);
+    put_varint(buf, value);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(buf, field << 3 | 2);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn put_fixed32(buf: &mut Vec<u8>, field: u64, value: u32) {
+    put_varint(buf, field << 3 | 5);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_time(buf: &mut Vec<u8>, sec_field: u64, nsec_field: u64, time: SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    put_uint(buf, sec_field, since_epoch.as_secs());
+    put_fixed32(buf, nsec_field, since_epoch.subsec_nanos());
+}
+
+// A single CLIENT_RESPONSE (AUTH_RESPONSE for our zones) with both messages. What dnstap has
+// no field for, the cache and the upstreams, goes into extra as text
+fn dnstap_message(query: &LoggedQuery) -> Vec<u8> {
+    let mut message = Vec::new();
+    put_uint(&mut message, 1, if query.authoritative { 2 } else { 6 });
+    let address = match query.client.ip() {
+        IpAddr::V4(addr) => {
+            put_uint(&mut message, 2, 1);
+            addr.octets().to_vec()
+        }
+        IpAddr::V6(addr) => {
+            put_uint(&mut message, 2, 2);
+            addr.octets().to_vec()
+        }
+    };
+    put_uint(
+        &mut message,
+        3,
+        if query.transport == Transport::Udp {
+            1
+        } else {
+            2
+        },
+    );
+    put_bytes(&mut message, 4, &address);
+    put_uint(&mut message, 6, u64::from(query.client.port()));
+    put_time(&mut message, 8, 9, query.received);
+    put_bytes(&mut message, 10, query.query);
+    put_time(&mut message, 12, 13, query.answered);
+    put_bytes(&mut message, 14, query.response);
+
+    let extra = query.trace.to_string();
+
+    let mut dnstap = Vec::new();
+    put_bytes(
+        &mut dnstap,
+        2,
+        concat!("swdns ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    put_bytes(&mut dnstap, 3, extra.as_bytes());
+    put_bytes(&mut dnstap, 14, &message);
+    // Type MESSAGE
+    put_uint(&mut dnstap, 15, 1);
+
+    dnstap
+}
+
+fn data_frame(payload: &[u8]) -> Vec<u8> {
+    [&(payload.len() as u32).to_be_bytes()[..], payload].concat()
+}
+
+// An escape of a zero length, then the length of the control frame, its type and the content type
+fn control_frame(frame_type: u32) -> Vec<u8> {
+    let mut control = frame_type.to_be_bytes().to_vec();
+    control.extend(FSTRM_CONTENT_TYPE.to_be_bytes());
+    control.extend((DNSTAP_CONTENT_TYPE.len() as u32).to_be_bytes());
+    control.extend(DNSTAP_CONTENT_TYPE);
+
+    [&0u32.to_be_bytes()[..], &data_frame(&control)].concat()
+}
+
+fn read_accept(stream: &mut Stream) -> io::Result<()> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let escape = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if escape != 0 || !(4..=MAX_CONTROL_FRAME).contains(&len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "dnstap collector didn't answer with a control frame",
+        ));
+    }
+
+    let mut control = vec![0; len];
+    stream.read_exact(&mut control)?;
+    if control[..4] != FSTRM_ACCEPT.to_be_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "dnstap collector didn't accept the stream",
+        ));
+    }
+
+    Ok(())
+}
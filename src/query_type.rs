@@ -1,22 +1,54 @@
-#[derive(Debug, PartialEq, Eq, Clone, Hash, Copy)]
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Copy, PartialOrd, Ord)]
 pub enum QueryType {
     UNKNOWN(u16),
-    A,     // 1
-    NS,    // 2
-    CNAME, // 5
-    MX,    // 15
-    AAAA,  // 28
+    A,      // 1
+    NS,     // 2
+    CNAME,  // 5
+    SOA,    // 6
+    PTR,    // 12
+    MX,     // 15
+    TXT,    // 16
+    AAAA,   // 28
+    SRV,    // 33
+    NAPTR,  // 35
+    OPT,    // 41
+    DS,     // 43
+    SSHFP,  // 44
+    RRSIG,  // 46
+    NSEC,   // 47
+    DNSKEY, // 48
+    NSEC3,  // 50
+    SVCB,   // 64
+    HTTPS,  // 65
+    CAA,    // 257
 }
 
-impl Into<u16> for QueryType {
-    fn into(self) -> u16 {
-        match self {
+impl From<QueryType> for u16 {
+    fn from(value: QueryType) -> Self {
+        match value {
             QueryType::UNKNOWN(x) => x,
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::SSHFP => 44,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::CAA => 257,
         }
     }
 }
@@ -27,9 +59,66 @@ impl From<u16> for QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            44 => QueryType::SSHFP,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(value),
         }
     }
 }
+
+impl QueryType {
+    // Mnemonic used in zone files, e.g. "AAAA", or the generic TYPEnnn from RFC 3597
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_uppercase();
+        if let Some(Ok(value)) = name.strip_prefix("TYPE").map(str::parse::<u16>) {
+            return Some(QueryType::from(value));
+        }
+
+        match name.as_str() {
+            "A" => Some(QueryType::A),
+            "NS" => Some(QueryType::NS),
+            "CNAME" => Some(QueryType::CNAME),
+            "SOA" => Some(QueryType::SOA),
+            "PTR" => Some(QueryType::PTR),
+            "MX" => Some(QueryType::MX),
+            "TXT" => Some(QueryType::TXT),
+            "AAAA" => Some(QueryType::AAAA),
+            "SRV" => Some(QueryType::SRV),
+            "NAPTR" => Some(QueryType::NAPTR),
+            "DS" => Some(QueryType::DS),
+            "SSHFP" => Some(QueryType::SSHFP),
+            "RRSIG" => Some(QueryType::RRSIG),
+            "NSEC" => Some(QueryType::NSEC),
+            "DNSKEY" => Some(QueryType::DNSKEY),
+            "NSEC3" => Some(QueryType::NSEC3),
+            "SVCB" => Some(QueryType::SVCB),
+            "HTTPS" => Some(QueryType::HTTPS),
+            "CAA" => Some(QueryType::CAA),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryType::UNKNOWN(value) => write!(f, "TYPE{}", value),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::HashSet,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::{
+    dns_records::DnsRecord,
+    errors::{ZoneError, ZoneResult},
+    name::Name,
+    zone_file::ZoneFileParser,
+};
+
+// The 13 root servers as published by IANA in named.root. They only have to be good enough
+// to get the priming query answered, the current set comes from the root itself. The names
+// are only there for whoever updates the list
+const ROOT_SERVERS: [(&str, Ipv4Addr, Ipv6Addr); 13] = [
+    (
+        "a.root-servers.net",
+        Ipv4Addr::new(198, 41, 0, 4),
+        Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "b.root-servers.net",
+        Ipv4Addr::new(170, 247, 170, 2),
+        Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),
+    ),
+    (
+        "c.root-servers.net",
+        Ipv4Addr::new(192, 33, 4, 12),
+        Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),
+    ),
+    (
+        "d.root-servers.net",
+        Ipv4Addr::new(199, 7, 91, 13),
+        Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd),
+    ),
+    (
+        "e.root-servers.net",
+        Ipv4Addr::new(192, 203, 230, 10),
+        Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe),
+    ),
+    (
+        "f.root-servers.net",
+        Ipv4Addr::new(192, 5, 5, 241),
+        Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf),
+    ),
+    (
+        "g.root-servers.net",
+        Ipv4Addr::new(192, 112, 36, 4),
+        Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d),
+    ),
+    (
+        "h.root-servers.net",
+        Ipv4Addr::new(198, 97, 190, 53),
+        Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "i.root-servers.net",
+        Ipv4Addr::new(192, 36, 148, 17),
+        Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "j.root-servers.net",
+        Ipv4Addr::new(192, 58, 128, 30),
+        Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "k.root-servers.net",
+        Ipv4Addr::new(193, 0, 14, 129),
+        Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1),
+    ),
+    (
+        "l.root-servers.net",
+        Ipv4Addr::new(199, 7, 83, 42),
+        Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42),
+    ),
+    (
+        "m.root-servers.net",
+        Ipv4Addr::new(202, 12, 27, 33),
+        Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35),
+    ),
+];
+
+pub fn builtin() -> Vec<IpAddr> {
+    ROOT_SERVERS
+        .iter()
+        .flat_map(|(_, ipv4, ipv6)| [IpAddr::V4(*ipv4), IpAddr::V6(*ipv6)])
+        .collect()
+}
+
+// named.root is a master file with the NS records of the root and the addresses of their hosts,
+// addresses of anything else are ignored
+pub fn load(path: &Path) -> ZoneResult<Vec<IpAddr>> {
+    let content = fs::read_to_string(path).map_err(|error| ZoneError::Read {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let records = ZoneFileParser::new(path, &Name::root()).parse(&content)?;
+
+    let hosts: HashSet<&Name> = records
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::NS { domain, host, .. } if domain.is_root() => Some(host),
+            _ => None,
+        })
+        .collect();
+
+    let addrs: Vec<IpAddr> = records
+        .iter()
+        .filter(|record| hosts.contains(record.domain()))
+        .filter_map(|record| match record {
+            DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+            DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(ZoneError::Invalid {
+            origin: Name::root().to_string(),
+            reason: format!("{:?} has no addresses of root name servers", path),
+        });
+    }
+
+    Ok(addrs)
+}
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Weight of the newest sample in the smoothed rtt, in eighths like TCP does it
+const NEW_SAMPLE_WEIGHT: u32 = 2;
+// A single answer with the wrong case may be spoofed, only this many of them within the window
+// turn 0x20 off for the server
+const CASE_MISMATCH_LIMIT: u32 = 3;
+const CASE_MISMATCH_WINDOW: Duration = Duration::from_secs(300);
+// After that the server is probed with 0x20 again
+const CASE_BLIND_DURATION: Duration = Duration::from_secs(3600);
+
+struct CaseMismatches {
+    count: u32,
+    window_start: Instant,
+    // Set once the limit is reached
+    blind_until: Option<Instant>,
+}
+
+// What we learned about the servers we talk to. Smoothed round trip times are used to ask
+// the fastest ones first
+#[derive(Default)]
+pub struct ServerStats {
+    srtts: Mutex<HashMap<SocketAddr, Duration>>,
+    // Servers which answer with the question in another case than it was asked, the blind ones
+    // get queries without 0x20 randomisation for a while
+    case_mismatches: Mutex<HashMap<SocketAddr, CaseMismatches>>,
+}
+
+impl ServerStats {
+    pub fn record_success(&self, server: SocketAddr, rtt: Duration) {
+        let mut srtts = self.srtts.lock().unwrap_or_else(|e| e.into_inner());
+
+        let srtt = match srtts.get(&server) {
+            Some(srtt) => (*srtt * (8 - NEW_SAMPLE_WEIGHT) + rtt * NEW_SAMPLE_WEIGHT) / 8,
+            None => rtt,
+        };
+        srtts.insert(server, srtt);
+    }
+
+    // A server which didn't answer is pushed behind everyone who did
+    pub fn record_failure(&self, server: SocketAddr, timeout: Duration) {
+        let mut srtts = self.srtts.lock().unwrap_or_else(|e| e.into_inner());
+
+        let srtt = srtts.get(&server).copied().unwrap_or_default();
+        srtts.insert(server, srtt.max(timeout) * 2);
+    }
+
+    // Servers we haven't talked to yet go first, so they get measured
+    pub fn sort_fastest(&self, servers: &mut [SocketAddr]) {
+        let srtts = self.srtts.lock().unwrap_or_else(|e| e.into_inner());
+
+        servers.sort_by_key(|server| srtts.get(server).copied().unwrap_or_default());
+    }
+
+    pub fn record_case_mismatch(&self, server: SocketAddr) {
+        let mut mismatches = self
+            .case_mismatches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let now = Instant::now();
+        let entry = mismatches.entry(server).or_insert(CaseMismatches {
+            count: 0,
+            window_start: now,
+            blind_until: None,
+        });
+        if entry.blind_until.is_some() {
+            return;
+        }
+        if now.duration_since(entry.window_start) > CASE_MISMATCH_WINDOW {
+            entry.count = 0;
+            entry.window_start = now;
+        }
+
+        entry.count += 1;
+        if entry.count >= CASE_MISMATCH_LIMIT {
+            log::info!(
+                "{} doesn't echo the case of questions, turning 0x20 off for it",
+                server
+            );
+            entry.blind_until = Some(now + CASE_BLIND_DURATION);
+        }
+    }
+
+    pub fn echoes_case(&self, server: SocketAddr) -> bool {
+        let mut mismatches = self
+            .case_mismatches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let Some(blind_until) = mismatches.get(&server).and_then(|entry| entry.blind_until) else {
+            return true;
+        };
+        if Instant::now() < blind_until {
+            return false;
+        }
+
+        // Probed again, it may have been fixed or it was never blind in the first place
+        mismatches.remove(&server);
+        true
+    }
+}
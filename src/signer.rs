@@ -0,0 +1,708 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::{
+    rand::SystemRandom,
+    signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair},
+};
+use serde::Deserialize;
+
+use crate::{
+    dns_records::{base32hex_encode, DnsRecord},
+    dnssec::{self, ECDSAP256SHA256, ED25519, NSEC3_SHA1, SHA256, ZONE_KEY},
+    errors::{ZoneError, ZoneResult},
+    name::Name,
+    query_class::QueryClass,
+    query_type::QueryType,
+    zone_file::ZoneFileParser,
+};
+
+// Keys with the SEP bit are the KSKs, the DS in the parent points at them (RFC 4034 section 2.1.1)
+const SECURE_ENTRY_POINT: u16 = 0x0001;
+const DNSKEY_PROTOCOL: u8 = 3;
+const DNSKEY_TTL: u32 = 3600;
+
+// Signatures are made for every answer, they don't have to last. The inception is back-dated
+// for validators whose clock is behind ours
+const SIGNATURE_VALIDITY: u32 = 7 * 24 * 3600;
+const INCEPTION_SKEW: u32 = 3600;
+
+const PRIVATE_KEY: &str = "PrivateKey:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningAlgorithm {
+    Ecdsap256sha256,
+    Ed25519,
+}
+
+impl SigningAlgorithm {
+    fn number(self) -> u8 {
+        match self {
+            SigningAlgorithm::Ecdsap256sha256 => ECDSAP256SHA256,
+            SigningAlgorithm::Ed25519 => ED25519,
+        }
+    }
+}
+
+enum SigningKeyPair {
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl SigningKeyPair {
+    fn from_pkcs8(algorithm: u8, pkcs8: &[u8]) -> Result<Self, String> {
+        match algorithm {
+            ECDSAP256SHA256 => EcdsaKeyPair::from_pkcs8(
+                &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                pkcs8,
+                &SystemRandom::new(),
+            )
+            .map(SigningKeyPair::Ecdsa)
+            .map_err(|e| e.to_string()),
+            ED25519 => Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+                .map(SigningKeyPair::Ed25519)
+                .map_err(|e| e.to_string()),
+            other => Err(format!(
+                "algorithm {} can't be used for signing, only {} and {}",
+                other, ECDSAP256SHA256, ED25519
+            )),
+        }
+    }
+
+    // As the DNSKEY has it, P-256 points without the uncompressed point prefix (RFC 6605 section 4)
+    fn public_key(&self) -> Vec<u8> {
+        match self {
+            SigningKeyPair::Ecdsa(pair) => pair.public_key().as_ref()[1..].to_vec(),
+            SigningKeyPair::Ed25519(pair) => pair.public_key().as_ref().to_vec(),
+        }
+    }
+
+    fn sign(&self, data: &[u8], rng: &SystemRandom) -> Option<Vec<u8>> {
+        match self {
+            SigningKeyPair::Ecdsa(pair) => {
+                pair.sign(rng, data).ok().map(|sig| sig.as_ref().to_vec())
+            }
+            SigningKeyPair::Ed25519(pair) => Some(pair.sign(data).as_ref().to_vec()),
+        }
+    }
+}
+
+// A key file is the DNSKEY record in master file format and a "PrivateKey:" line with the
+// base64 PKCS#8 document of the private key
+pub struct SigningKey {
+    dnskey: DnsRecord,
+    key_tag: u16,
+    algorithm: u8,
+    ksk: bool,
+    key_pair: SigningKeyPair,
+}
+
+impl SigningKey {
+    pub fn load(path: &Path, origin: &Name) -> ZoneResult<Self> {
+        let content = fs::read_to_string(path).map_err(|error| ZoneError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        Self::parse(path, origin, &content)
+    }
+
+    // `path` is only used in errors
+    pub fn parse(path: &Path, origin: &Name, content: &str) -> ZoneResult<Self> {
+        let invalid = |reason: String| ZoneError::Invalid {
+            origin: origin.to_string(),
+            reason: format!("key file {:?} {}", path, reason),
+        };
+
+        // The private key line is blanked out instead of removed, so errors keep their line numbers
+        let mut private_key = None;
+        let public: Vec<&str> = content
+            .lines()
+            .map(|line| match line.trim_start().strip_prefix(PRIVATE_KEY) {
+                Some(key) => {
+                    private_key = Some(key.trim());
+                    ""
+                }
+                None => line,
+            })
+            .collect();
+        let private_key = private_key.ok_or_else(|| invalid("has no private key".to_string()))?;
+        let pkcs8 = BASE64
+            .decode(private_key)
+            .map_err(|e| invalid(format!("has a private key that isn't base64 - {}", e)))?;
+
+        let records = ZoneFileParser::new(path, origin).parse(&public.join("\n"))?;
+        let [dnskey @ DnsRecord::DNSKEY {
+            domain,
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+            ..
+        }] = records.as_slice()
+        else {
+            return Err(invalid("must have exactly one DNSKEY record".to_string()));
+        };
+
+        if domain != origin {
+            return Err(invalid(format!("has the DNSKEY of {}", domain)));
+        }
+        if flags & ZONE_KEY == 0 || *protocol != DNSKEY_PROTOCOL {
+            return Err(invalid("doesn't have a DNSSEC zone key".to_string()));
+        }
+
+        let key_pair = SigningKeyPair::from_pkcs8(*algorithm, &pkcs8)
+            .map_err(|reason| invalid(format!("has an unusable private key - {}", reason)))?;
+        if key_pair.public_key() != *public_key {
+            return Err(invalid(
+                "has a private key that doesn't belong to the DNSKEY".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            dnskey: dnskey.clone(),
+            key_tag: dnssec::key_tag(dnskey).unwrap_or_default(),
+            algorithm: *algorithm,
+            ksk: flags & SECURE_ENTRY_POINT != 0,
+            key_pair,
+        })
+    }
+
+    pub fn dnskey(&self) -> &DnsRecord {
+        &self.dnskey
+    }
+}
+
+// A new key file for the zone, with the DS to hand to the parent in a comment
+pub fn generate_key(zone: &Name, ksk: bool, algorithm: SigningAlgorithm) -> Result<String, String> {
+    let rng = SystemRandom::new();
+    let pkcs8 = match algorithm {
+        SigningAlgorithm::Ecdsap256sha256 => {
+            EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+        }
+        SigningAlgorithm::Ed25519 => Ed25519KeyPair::generate_pkcs8(&rng),
+    }
+    .map_err(|e| format!("Can't generate a key - {}", e))?;
+    let key_pair = SigningKeyPair::from_pkcs8(algorithm.number(), pkcs8.as_ref())?;
+
+    let dnskey = DnsRecord::DNSKEY {
+        domain: zone.clone(),
+        class: QueryClass::IN,
+        flags: if ksk {
+            ZONE_KEY | SECURE_ENTRY_POINT
+        } else {
+            ZONE_KEY
+        },
+        protocol: DNSKEY_PROTOCOL,
+        algorithm: algorithm.number(),
+        public_key: key_pair.public_key(),
+        ttl: DNSKEY_TTL,
+    };
+
+    let mut file = format!(
+        "; {} of {}, {:?}, key tag {}\n",
+        if ksk { "KSK" } else { "ZSK" },
+        zone,
+        algorithm,
+        dnssec::key_tag(&dnskey).unwrap_or_default()
+    );
+    if let Some(ds) = dnssec::ds_of(&dnskey, SHA256) {
+        file.push_str(&format!("; DS for the parent zone: {}\n", ds));
+    }
+    file.push_str(&format!("{}\n", dnskey));
+    file.push_str(&format!(
+        "{} {}\n",
+        PRIVATE_KEY,
+        BASE64.encode(pkcs8.as_ref())
+    ));
+
+    Ok(file)
+}
+
+// How the zone proves what it doesn't have
+enum Chain {
+    // Names with records in canonical order with the types they have (RFC 4034 section 4)
+    Nsec(Vec<(Name, Vec<QueryType>)>),
+    // Hashes of all names, empty non-terminals too, in order with their types (RFC 5155)
+    Nsec3(Vec<(Vec<u8>, Vec<QueryType>)>),
+}
+
+// Signs the answers of a zone as they are handed out and makes the NSEC/NSEC3 records of its
+// negative answers
+pub struct ZoneSigner {
+    origin: Name,
+    keys: Vec<SigningKey>,
+    chain: Chain,
+    // Of the NSEC/NSEC3 records, same as that of the negative SOA (RFC 4034 section 4)
+    nsec_ttl: u32,
+    rng: SystemRandom,
+}
+
+impl ZoneSigner {
+    // `names` are the owners of the zone, apex first, with the types each has. Empty
+    // non-terminals come with no types and glue under zone cuts is left out
+    pub fn new(
+        origin: &Name,
+        keys: Vec<SigningKey>,
+        names: Vec<(Name, Vec<QueryType>)>,
+        nsec3: bool,
+        nsec_ttl: u32,
+    ) -> Self {
+        let chain = if nsec3 {
+            let mut hashes: Vec<(Vec<u8>, Vec<QueryType>)> = names
+                .into_iter()
+                .map(|(name, mut types)| {
+                    // Unsigned delegations have no RRSIG
+                    let unsigned = name != *origin
+                        && types.contains(&QueryType::NS)
+                        && !types.contains(&QueryType::DS);
+                    if !types.is_empty() && !unsigned {
+                        types.push(QueryType::RRSIG);
+                    }
+                    (dnssec::nsec3_hash(&name, &[], 0), types)
+                })
+                .collect();
+            hashes.sort_by(|a, b| a.0.cmp(&b.0));
+            Chain::Nsec3(hashes)
+        } else {
+            let mut names: Vec<(Name, Vec<QueryType>)> = names
+                .into_iter()
+                .filter(|(_, types)| !types.is_empty())
+                .map(|(name, mut types)| {
+                    types.extend([QueryType::NSEC, QueryType::RRSIG]);
+                    (name, types)
+                })
+                .collect();
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            Chain::Nsec(names)
+        };
+
+        Self {
+            origin: origin.clone(),
+            keys,
+            chain,
+            nsec_ttl,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    // RRSIGs over every RRset of the records. `wildcard` is the wildcard the records were
+    // expanded from, it tells the validator how many labels were signed
+    pub fn sign(&self, records: &[DnsRecord], wildcard: Option<&Name>) -> Vec<DnsRecord> {
+        // KSKs sign the key set and ZSKs everything else, unless the zone has only one kind
+        let has_ksk = self.keys.iter().any(|key| key.ksk);
+        let has_zsk = self.keys.iter().any(|key| !key.ksk);
+        let now = dnssec::now();
+
+        let mut signatures = Vec::new();
+        for rrset in dnssec::rrsets(records) {
+            let ksk = rrset.query_type == QueryType::DNSKEY;
+            let labels = match wildcard {
+                Some(wildcard) => wildcard.label_count() - 1,
+                None => rrset.owner.label_count() - usize::from(rrset.owner.is_wildcard()),
+            };
+
+            for key in &self.keys {
+                if key.ksk != ksk && has_ksk && has_zsk {
+                    continue;
+                }
+
+                let mut rrsig = DnsRecord::RRSIG {
+                    domain: rrset.owner.clone(),
+                    class: QueryClass::IN,
+                    type_covered: rrset.query_type,
+                    algorithm: key.algorithm,
+                    labels: labels as u8,
+                    original_ttl: rrset.records[0].ttl(),
+                    expiration: now.wrapping_add(SIGNATURE_VALIDITY),
+                    inception: now.wrapping_sub(INCEPTION_SKEW),
+                    key_tag: key.key_tag,
+                    signer_name: self.origin.clone(),
+                    signature: Vec::new(),
+                    ttl: rrset.records[0].ttl(),
+                };
+
+                let Some(sig) = dnssec::signed_data(&rrset.records, &rrsig)
+                    .and_then(|data| key.key_pair.sign(&data, &self.rng))
+                else {
+                    log::warn!(
+                        "Can't sign {} {} with key {}",
+                        rrset.owner,
+                        rrset.query_type,
+                        key.key_tag
+                    );
+                    continue;
+                };
+                if let DnsRecord::RRSIG { signature, .. } = &mut rrsig {
+                    *signature = sig;
+                }
+                signatures.push(rrsig);
+            }
+        }
+
+        signatures
+    }
+
+    // NXDOMAIN: neither the name nor the wildcard at its closest encloser exists
+    pub fn nxdomain_proof(&self, qname: &Name, encloser: &Name) -> Vec<DnsRecord> {
+        let wildcard = encloser.wildcard().ok();
+        let mut links = BTreeSet::new();
+        match &self.chain {
+            Chain::Nsec(names) => {
+                links.insert(covering(names, qname));
+                if let Some(wildcard) = &wildcard {
+                    links.insert(covering(names, wildcard));
+                }
+            }
+            Chain::Nsec3(hashes) => {
+                links.extend(matching_hash(hashes, encloser));
+                links.insert(covering_hash(hashes, &next_closer(qname, encloser)));
+                if let Some(wildcard) = &wildcard {
+                    links.insert(covering_hash(hashes, wildcard));
+                }
+            }
+        }
+        self.proof(links)
+    }
+
+    // NODATA: the name exists without the type, also used for the missing DS of a delegation
+    pub fn nodata_proof(&self, name: &Name) -> Vec<DnsRecord> {
+        let link = match &self.chain {
+            // Empty non-terminals have no NSEC, the one covering them shows there is nothing
+            Chain::Nsec(names) => names
+                .binary_search_by(|(owner, _)| owner.cmp(name))
+                .unwrap_or_else(|_| covering(names, name)),
+            Chain::Nsec3(hashes) => match matching_hash(hashes, name) {
+                Some(link) => link,
+                // Not in the chain, e.g. below a zone cut. What is there is proven the way an
+                // NXDOMAIN is, from the closest encloser down
+                None => {
+                    let encloser = (1..name.label_count())
+                        .map(|skip| name.suffix(skip))
+                        .find(|encloser| {
+                            encloser.is_subdomain_of(&self.origin)
+                                && matching_hash(hashes, encloser).is_some()
+                        })
+                        .unwrap_or_else(|| self.origin.clone());
+                    return self.nxdomain_proof(name, &encloser);
+                }
+            },
+        };
+        self.proof([link].into())
+    }
+
+    // An answer expanded from the wildcard at the closest encloser: the name itself doesn't
+    // exist. With `nodata` the wildcard doesn't have the type either
+    pub fn wildcard_proof(&self, qname: &Name, encloser: &Name, nodata: bool) -> Vec<DnsRecord> {
+        let wildcard = encloser.wildcard().ok();
+        let mut links = BTreeSet::new();
+        match &self.chain {
+            Chain::Nsec(names) => {
+                links.insert(covering(names, qname));
+                if let Some(wildcard) = wildcard.as_ref().filter(|_| nodata) {
+                    links.extend(
+                        names
+                            .binary_search_by(|(owner, _)| owner.cmp(wildcard))
+                            .ok(),
+                    );
+                }
+            }
+            Chain::Nsec3(hashes) => {
+                links.insert(covering_hash(hashes, &next_closer(qname, encloser)));
+                if let Some(wildcard) = wildcard.as_ref().filter(|_| nodata) {
+                    links.extend(matching_hash(hashes, encloser));
+                    links.extend(matching_hash(hashes, wildcard));
+                }
+            }
+        }
+        self.proof(links)
+    }
+
+    // The NSEC/NSEC3 records at the given positions of the chain, signed
+    fn proof(&self, links: BTreeSet<usize>) -> Vec<DnsRecord> {
+        let records: Vec<DnsRecord> = links
+            .into_iter()
+            .filter_map(|link| self.chain_record(link))
+            .collect();
+        let signatures = self.sign(&records, None);
+        records.into_iter().chain(signatures).collect()
+    }
+
+    fn chain_record(&self, link: usize) -> Option<DnsRecord> {
+        match &self.chain {
+            Chain::Nsec(names) => {
+                let (name, types) = names.get(link)?;
+                Some(DnsRecord::NSEC {
+                    domain: name.clone(),
+                    class: QueryClass::IN,
+                    next_domain: names[(link + 1) % names.len()].0.clone(),
+                    types: types.clone(),
+                    ttl: self.nsec_ttl,
+                })
+            }
+            Chain::Nsec3(hashes) => {
+                let (hash, types) = hashes.get(link)?;
+                let owner = Name::from_labels(
+                    [
+                        vec![base32hex_encode(hash).to_ascii_lowercase().into_bytes()],
+                        self.origin.labels().to_vec(),
+                    ]
+                    .concat(),
+                )
+                .ok()?;
+                Some(DnsRecord::NSEC3 {
+                    domain: owner,
+                    class: QueryClass::IN,
+                    hash_algorithm: NSEC3_SHA1,
+                    flags: 0,
+                    iterations: 0,
+                    salt: Vec::new(),
+                    next_hashed: hashes[(link + 1) % hashes.len()].0.clone(),
+                    types: types.clone(),
+                    ttl: self.nsec_ttl,
+                })
+            }
+        }
+    }
+}
+
+// The name one label below the closest encloser on the way to the qname
+fn next_closer(qname: &Name, encloser: &Name) -> Name {
+    qname.suffix(qname.label_count() - encloser.label_count() - 1)
+}
+
+// Position of the last name before `name`, the first name of the zone wraps around to the last
+fn covering(names: &[(Name, Vec<QueryType>)], name: &Name) -> usize {
+    match names.binary_search_by(|(owner, _)| owner.cmp(name)) {
+        Ok(link) => link,
+        Err(0) => names.len() - 1,
+        Err(link) => link - 1,
+    }
+}
+
+fn matching_hash(hashes: &[(Vec<u8>, Vec<QueryType>)], name: &Name) -> Option<usize> {
+    let hash = dnssec::nsec3_hash(name, &[], 0);
+    hashes.binary_search_by(|(owner, _)| owner.cmp(&hash)).ok()
+}
+
+fn covering_hash(hashes: &[(Vec<u8>, Vec<QueryType>)], name: &Name) -> usize {
+    let hash = dnssec::nsec3_hash(name, &[], 0);
+    match hashes.binary_search_by(|(owner, _)| owner.cmp(&hash)) {
+        Ok(link) => link,
+        Err(0) => hashes.len() - 1,
+        Err(link) => link - 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{
+        dnssec::{RrSet, Security},
+        test_zone::{self, name},
+    };
+
+    fn a_record(domain: &str) -> DnsRecord {
+        DnsRecord::A {
+            domain: name(domain),
+            class: QueryClass::IN,
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        }
+    }
+
+    // example. with www, the empty non-terminal b above a.b, the unsigned delegation sub and
+    // the wildcard *.wild, itself under an empty non-terminal
+    fn signer(nsec3: bool) -> (ZoneSigner, Vec<DnsRecord>) {
+        let origin = name("example.");
+        let keys = test_zone::keys(&origin);
+        let dnskeys = keys.iter().map(|key| key.dnskey().clone()).collect();
+        let owners = vec![
+            (
+                origin.clone(),
+                vec![QueryType::NS, QueryType::SOA, QueryType::DNSKEY],
+            ),
+            (name("www.example."), vec![QueryType::A]),
+            (name("b.example."), vec![]),
+            (name("a.b.example."), vec![QueryType::A]),
+            (name("sub.example."), vec![QueryType::NS]),
+            (name("wild.example."), vec![]),
+            (name("*.wild.example."), vec![QueryType::A]),
+        ];
+
+        (ZoneSigner::new(&origin, keys, owners, nsec3, 300), dnskeys)
+    }
+
+    fn verify(rrset: &RrSet, keys: &[DnsRecord]) -> Result<(), String> {
+        dnssec::verify_rrset(rrset, keys, dnssec::now()).map(|_| ())
+    }
+
+    // Every RRset of the records is signed and verifies with the keys of the zone
+    fn assert_verifies(records: &[DnsRecord], keys: &[DnsRecord]) {
+        assert!(!records.is_empty());
+        for rrset in dnssec::rrsets(records) {
+            if let Err(reason) = verify(&rrset, keys) {
+                panic!("{}", reason);
+            }
+        }
+    }
+
+    fn signed(signer: &ZoneSigner, records: Vec<DnsRecord>) -> Vec<DnsRecord> {
+        let signatures = signer.sign(&records, None);
+        records.into_iter().chain(signatures).collect()
+    }
+
+    #[test]
+    fn signatures_verify_with_the_zone_keys() {
+        let (signer, keys) = signer(false);
+
+        assert_verifies(&signed(&signer, vec![a_record("www.example.")]), &keys);
+        assert_verifies(&signed(&signer, keys.clone()), &keys);
+    }
+
+    #[test]
+    fn ksk_signs_only_the_key_set() {
+        let (signer, keys) = signer(false);
+        let tag = |ksk: bool| {
+            keys.iter()
+                .find(|key| matches!(key, DnsRecord::DNSKEY { flags, .. } if (flags & SECURE_ENTRY_POINT != 0) == ksk))
+                .and_then(dnssec::key_tag)
+                .unwrap()
+        };
+        let tags = |records: &[DnsRecord]| -> Vec<u16> {
+            signer
+                .sign(records, None)
+                .iter()
+                .filter_map(|rrsig| match rrsig {
+                    DnsRecord::RRSIG { key_tag, .. } => Some(*key_tag),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(tags(&keys), vec![tag(true)]);
+        assert_eq!(tags(&[a_record("www.example.")]), vec![tag(false)]);
+    }
+
+    #[test]
+    fn changed_records_dont_verify() {
+        let (signer, keys) = signer(false);
+        let mut records = signed(&signer, vec![a_record("www.example.")]);
+        if let DnsRecord::A { addr, .. } = &mut records[0] {
+            *addr = Ipv4Addr::new(192, 0, 2, 66);
+        }
+
+        assert!(verify(&dnssec::rrsets(&records)[0], &keys).is_err());
+    }
+
+    #[test]
+    fn wildcard_answers_verify_as_the_wildcard() {
+        for nsec3 in [false, true] {
+            let (signer, keys) = signer(nsec3);
+            let qname = name("x.y.wild.example.");
+            let wildcard = name("*.wild.example.");
+            let encloser = name("wild.example.");
+
+            let answer = vec![a_record("x.y.wild.example.")];
+            let signatures = signer.sign(&answer, Some(&wildcard));
+            let records: Vec<DnsRecord> = answer.into_iter().chain(signatures).collect();
+            assert_verifies(&records, &keys);
+
+            let rrsets = dnssec::rrsets(&records);
+            let rrsig = dnssec::verify_rrset(&rrsets[0], &keys, dnssec::now()).unwrap();
+            assert_eq!(
+                dnssec::wildcard_source(&qname, rrsig),
+                Some(encloser.clone())
+            );
+
+            let proof = signer.wildcard_proof(&qname, &encloser, false);
+            assert_verifies(&proof, &keys);
+            assert_eq!(
+                dnssec::prove_wildcard_answer(&proof, &qname, &encloser),
+                Security::Secure
+            );
+        }
+    }
+
+    #[test]
+    fn nxdomain_is_proven() {
+        for nsec3 in [false, true] {
+            let (signer, keys) = signer(nsec3);
+
+            for (qname, encloser) in [
+                ("nx.example.", "example."),
+                ("x.nx.example.", "example."),
+                ("x.www.example.", "www.example."),
+                ("x.b.example.", "b.example."),
+            ] {
+                let proof = signer.nxdomain_proof(&name(qname), &name(encloser));
+                assert_verifies(&proof, &keys);
+                assert_eq!(
+                    dnssec::prove_denial(&proof, &name(qname), QueryType::A, true),
+                    Security::Secure,
+                    "{} with nsec3 {}",
+                    qname,
+                    nsec3
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nodata_is_proven() {
+        for nsec3 in [false, true] {
+            let (signer, keys) = signer(nsec3);
+
+            for (qname, query_type) in [
+                ("www.example.", QueryType::MX),
+                ("example.", QueryType::A),
+                // Empty non-terminals
+                ("b.example.", QueryType::A),
+                ("wild.example.", QueryType::TXT),
+            ] {
+                let proof = signer.nodata_proof(&name(qname));
+                assert_verifies(&proof, &keys);
+                assert_eq!(
+                    dnssec::prove_denial(&proof, &name(qname), query_type, false),
+                    Security::Secure,
+                    "{} {} with nsec3 {}",
+                    qname,
+                    query_type,
+                    nsec3
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unsigned_delegations_have_no_ds() {
+        for nsec3 in [false, true] {
+            let (signer, keys) = signer(nsec3);
+            let cut = name("sub.example.");
+
+            let proof = signer.nodata_proof(&cut);
+            assert_verifies(&proof, &keys);
+            assert_eq!(
+                dnssec::prove_denial(&proof, &cut, QueryType::DS, false),
+                Security::Secure
+            );
+            assert!(dnssec::proves_insecure_delegation(&proof, &cut));
+        }
+    }
+
+    #[test]
+    fn names_outside_the_nsec3_chain_get_a_closest_encloser_proof() {
+        let (signer, keys) = signer(true);
+        let qname = name("host.sub.example.");
+
+        let proof = signer.nodata_proof(&qname);
+        assert_verifies(&proof, &keys);
+        assert_eq!(
+            dnssec::prove_denial(&proof, &qname, QueryType::A, true),
+            Security::Secure
+        );
+    }
+}
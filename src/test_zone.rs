@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use crate::{
+    dns_records::DnsRecord,
+    name::Name,
+    query_type::QueryType,
+    signer::{self, SigningAlgorithm, SigningKey},
+    zone::Zone,
+    zone_file::ZoneFileParser,
+};
+
+pub const ORIGIN: &str = "example.";
+
+// www, the empty non-terminal b above a.b, the unsigned delegation sub and a wildcard
+const ZONE: &str = "
+$TTL 3600
+@       SOA ns1 hostmaster 1 7200 3600 1209600 300
+        NS  ns1
+ns1     A   192.0.2.1
+www     A   192.0.2.10
+a.b     A   192.0.2.20
+sub     NS  ns.sub
+ns.sub  A   192.0.2.30
+*.wild  A   192.0.2.40
+";
+
+pub fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+// A KSK and a ZSK of the zone, one of each algorithm we sign with
+pub fn keys(origin: &Name) -> Vec<SigningKey> {
+    [
+        (true, SigningAlgorithm::Ecdsap256sha256),
+        (false, SigningAlgorithm::Ed25519),
+    ]
+    .into_iter()
+    .map(|(ksk, algorithm)| {
+        let key = signer::generate_key(origin, ksk, algorithm).unwrap();
+        SigningKey::parse(Path::new("test.key"), origin, &key).unwrap()
+    })
+    .collect()
+}
+
+// example. signed with new keys, denying with NSEC or NSEC3
+pub fn zone(nsec3: bool) -> Zone {
+    let origin = name(ORIGIN);
+    let keys = keys(&origin);
+
+    let mut records = ZoneFileParser::new(Path::new("example.zone"), &origin)
+        .parse(ZONE)
+        .unwrap();
+    records.extend(keys.iter().map(|key| key.dnskey().clone()));
+
+    let mut zone = Zone::from_records(&origin, records).unwrap();
+    zone.sign_with(keys, nsec3);
+    zone
+}
+
+// The DNSKEY set of the zone with its RRSIGs, as a client asking for it would get it
+pub fn dnskeys(zone: &Zone) -> Vec<DnsRecord> {
+    zone.lookup(&name(ORIGIN), QueryType::DNSKEY, true).answers
+}
@@ -0,0 +1,429 @@
+use std::{collections::HashMap, fs};
+
+use crate::{
+    config::ZoneConfig,
+    dns_packet::DnsPacket,
+    dns_records::DnsRecord,
+    errors::{ZoneError, ZoneResult},
+    name::Name,
+    query_type::QueryType,
+    result_code::ResultCode,
+    signer::{SigningKey, ZoneSigner},
+    zone_file::ZoneFileParser,
+};
+
+// CNAMEs inside our own zones are followed at most this many times
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Default)]
+struct ZoneNode {
+    records: Vec<DnsRecord>,
+    // Keyed by the next label down in lowercase, e.g. "www" under "example.com"
+    children: HashMap<Vec<u8>, ZoneNode>,
+}
+
+impl ZoneNode {
+    fn records_of(&self, query_type: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        self.records
+            .iter()
+            .filter(move |rec| rec.query_type() == query_type)
+    }
+
+    fn has(&self, query_type: QueryType) -> bool {
+        self.records_of(query_type).next().is_some()
+    }
+}
+
+enum Found<'a> {
+    Node(&'a ZoneNode),
+    // Name doesn't exist but a *.<closest encloser> does, the closest encloser comes along
+    Wildcard(&'a ZoneNode, Name),
+    // Name is at or below a zone cut, the node holds the NS records of the child zone
+    Delegation(&'a ZoneNode),
+    // With the closest encloser, the deepest name that does exist
+    NxDomain(Name),
+}
+
+// Records of a zone we are authoritative for, arranged by labels from the apex down
+pub struct Zone {
+    origin: Name,
+    apex: ZoneNode,
+    // Only zones with keys sign their answers
+    signer: Option<ZoneSigner>,
+}
+
+impl Zone {
+    pub fn load(config: &ZoneConfig) -> ZoneResult<Self> {
+        let content = fs::read_to_string(&config.file).map_err(|error| ZoneError::Read {
+            path: config.file.clone(),
+            error,
+        })?;
+
+        let mut records = ZoneFileParser::new(&config.file, &config.origin).parse(&content)?;
+        let keys = config
+            .keys
+            .iter()
+            .map(|path| SigningKey::load(path, &config.origin))
+            .collect::<ZoneResult<Vec<SigningKey>>>()?;
+        records.extend(keys.iter().map(|key| key.dnskey().clone()));
+
+        let mut zone = Self::from_records(&config.origin, records)?;
+
+        log::info!("Loaded zone {} from {:?}", zone.origin, config.file);
+
+        if !keys.is_empty() {
+            log::info!(
+                "Signing zone {} with {} keys and {}",
+                zone.origin,
+                keys.len(),
+                if config.nsec3 { "NSEC3" } else { "NSEC" }
+            );
+            zone.sign_with(keys, config.nsec3);
+        }
+
+        Ok(zone)
+    }
+
+    // Answers are signed from now on, the DNSKEYs of the keys have to be among the records
+    pub fn sign_with(&mut self, keys: Vec<SigningKey>, nsec3: bool) {
+        self.signer = Some(ZoneSigner::new(
+            &self.origin,
+            keys,
+            self.owners(),
+            nsec3,
+            self.negative_soa().ttl(),
+        ));
+    }
+
+    pub fn from_records(origin: &Name, records: Vec<DnsRecord>) -> ZoneResult<Self> {
+        let mut zone = Self {
+            origin: origin.clone(),
+            apex: ZoneNode::default(),
+            signer: None,
+        };
+
+        for rec in records {
+            let Some(labels) = zone.relative_labels(rec.domain()) else {
+                return Err(zone.invalid(format!("{} is out of the zone", rec.domain())));
+            };
+
+            let mut node = &mut zone.apex;
+            for label in labels {
+                node = node.children.entry(label).or_default();
+            }
+
+            if !node.records.contains(&rec) {
+                node.records.push(rec);
+            }
+        }
+
+        zone.validate()?;
+
+        Ok(zone)
+    }
+
+    fn invalid(&self, reason: impl Into<String>) -> ZoneError {
+        ZoneError::Invalid {
+            origin: self.origin.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    fn validate(&self) -> ZoneResult<()> {
+        if self.apex.records_of(QueryType::SOA).count() != 1 {
+            return Err(self.invalid("the apex must have exactly one SOA record"));
+        }
+        if !self.apex.has(QueryType::NS) {
+            return Err(self.invalid("the apex has no NS records"));
+        }
+
+        let mut nodes = vec![&self.apex];
+        while let Some(node) = nodes.pop() {
+            if !std::ptr::eq(node, &self.apex) && node.has(QueryType::SOA) {
+                return Err(self.invalid(format!(
+                    "SOA record of {} is below the apex",
+                    node.records[0].domain()
+                )));
+            }
+
+            // RFC 1034 3.6.2, an alias has no other data
+            if node.has(QueryType::CNAME) && node.records.len() > 1 {
+                return Err(self.invalid(format!(
+                    "CNAME at {} has other records next to it",
+                    node.records[0].domain()
+                )));
+            }
+
+            nodes.extend(node.children.values());
+        }
+
+        Ok(())
+    }
+
+    // Every name of the zone with the types it has, empty non-terminals with none. Names under
+    // a zone cut belong to the child zone and are left out
+    fn owners(&self) -> Vec<(Name, Vec<QueryType>)> {
+        let mut owners = Vec::new();
+        let mut nodes = vec![(self.origin.clone(), &self.apex)];
+        while let Some((name, node)) = nodes.pop() {
+            let mut types: Vec<QueryType> =
+                node.records.iter().map(|rec| rec.query_type()).collect();
+            types.sort();
+            types.dedup();
+
+            let cut = name != self.origin && node.has(QueryType::NS);
+            owners.push((name.clone(), types));
+            if cut {
+                continue;
+            }
+
+            for (label, child) in &node.children {
+                if let Ok(child_name) =
+                    Name::from_labels([vec![label.clone()], name.labels().to_vec()].concat())
+                {
+                    nodes.push((child_name, child));
+                }
+            }
+        }
+
+        owners
+    }
+
+    pub fn contains(&self, name: &Name) -> bool {
+        name.is_subdomain_of(&self.origin)
+    }
+
+    // Lowercase labels below the apex, the one closest to the apex first
+    fn relative_labels(&self, name: &Name) -> Option<Vec<Vec<u8>>> {
+        if !self.contains(name) {
+            return None;
+        }
+
+        let below = name.label_count() - self.origin.label_count();
+        Some(
+            name.labels()[..below]
+                .iter()
+                .rev()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+        )
+    }
+
+    // Exact node of the name, zone cuts are not taken into account
+    fn node(&self, name: &Name) -> Option<&ZoneNode> {
+        self.relative_labels(name)?
+            .into_iter()
+            .try_fold(&self.apex, |node, label| node.children.get(&label))
+    }
+
+    fn find(&self, qname: &Name, query_type: QueryType) -> Found<'_> {
+        let Some(labels) = self.relative_labels(qname) else {
+            return Found::NxDomain(self.origin.clone());
+        };
+
+        let depth = labels.len();
+        let mut node = &self.apex;
+        for (i, label) in labels.into_iter().enumerate() {
+            node = match node.children.get(&label) {
+                Some(child) => child,
+                None => {
+                    let encloser = qname.suffix(depth - i);
+                    return match node.children.get(b"*".as_slice()) {
+                        Some(wildcard) => Found::Wildcard(wildcard, encloser),
+                        None => Found::NxDomain(encloser),
+                    };
+                }
+            };
+
+            // NS records below the apex mark a zone cut, everything under it belongs to the child zone.
+            // Only the DS at the cut is the parent's to answer
+            if node.has(QueryType::NS) && !(query_type == QueryType::DS && i + 1 == depth) {
+                return Found::Delegation(node);
+            }
+        }
+
+        Found::Node(node)
+    }
+
+    // SOA for the authority section of negative answers, RFC 2308 section 3 ttl
+    fn negative_soa(&self) -> DnsRecord {
+        let mut soa = self.apex.records_of(QueryType::SOA).next().unwrap().clone();
+        if let DnsRecord::SOA { minimum, ttl, .. } = &mut soa {
+            *ttl = (*ttl).min(*minimum);
+        }
+
+        soa
+    }
+
+    // A/AAAA of a host we have data for, used for glue and the additional section
+    fn addresses(&self, host: &Name) -> Vec<DnsRecord> {
+        self.node(host)
+            .map(|node| {
+                node.records
+                    .iter()
+                    .filter(|rec| matches!(rec, DnsRecord::A { .. } | DnsRecord::AAAA { .. }))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn additional_for(&self, records: &[DnsRecord]) -> Vec<DnsRecord> {
+        records
+            .iter()
+            .filter_map(|rec| match rec {
+                DnsRecord::NS { host, .. }
+                | DnsRecord::MX { host, .. }
+                | DnsRecord::SRV { host, .. } => Some(host),
+                _ => None,
+            })
+            .flat_map(|host| self.addresses(host))
+            .collect()
+    }
+
+    // SOA of a negative answer, with the proof that there is nothing when signing
+    fn negative_answer(
+        &self,
+        packet: &mut DnsPacket,
+        signer: Option<&ZoneSigner>,
+        proof: impl FnOnce(&ZoneSigner) -> Vec<DnsRecord>,
+    ) {
+        let soa = self.negative_soa();
+        if let Some(signer) = signer {
+            packet
+                .authorities
+                .extend(signer.sign(std::slice::from_ref(&soa), None));
+            packet.authorities.extend(proof(signer));
+        }
+        packet.authorities.insert(0, soa);
+    }
+
+    // `dnssec_ok` is the DO bit of the client, only then answers of signed zones come with
+    // their signatures and proofs
+    pub fn lookup(&self, qname: &Name, query_type: QueryType, dnssec_ok: bool) -> DnsPacket {
+        let mut packet = DnsPacket::default();
+        packet.header.authoritative_answer = true;
+        packet.header.rescode = ResultCode::NOERROR;
+
+        let signer = self.signer.as_ref().filter(|_| dnssec_ok);
+        let mut qname = qname.clone();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let (node, encloser) = match self.find(&qname, query_type) {
+                Found::Node(node) => (node, None),
+                Found::Wildcard(node, encloser) => (node, Some(encloser)),
+                Found::Delegation(node) => {
+                    // Referrals aren't authoritative, unless we already answered a part of a CNAME chain
+                    packet.header.authoritative_answer = !packet.answers.is_empty();
+                    packet.authorities = node.records_of(QueryType::NS).cloned().collect();
+                    packet.resources = self.additional_for(&packet.authorities);
+
+                    // Signed is only the DS of the child, or the proof there is none
+                    if let Some(signer) = signer {
+                        let ds: Vec<DnsRecord> = node.records_of(QueryType::DS).cloned().collect();
+                        if ds.is_empty() {
+                            packet
+                                .authorities
+                                .extend(signer.nodata_proof(node.records[0].domain()));
+                        } else {
+                            packet.authorities.extend(signer.sign(&ds, None));
+                            packet.authorities.extend(ds);
+                        }
+                    }
+                    return packet;
+                }
+                Found::NxDomain(encloser) => {
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    self.negative_answer(&mut packet, signer, |signer| {
+                        signer.nxdomain_proof(&qname, &encloser)
+                    });
+                    return packet;
+                }
+            };
+            // Signed as the wildcard, which is known from the encloser even if the node has no
+            // records of its own
+            let wildcard = encloser
+                .as_ref()
+                .and_then(|encloser| encloser.wildcard().ok());
+
+            // Records of a wildcard are handed out as if they were owned by the name asked for
+            let synthesize = |rec: &DnsRecord| {
+                let mut rec = rec.clone();
+                if encloser.is_some() {
+                    rec.set_domain(qname.clone());
+                }
+                rec
+            };
+
+            let mut answers: Vec<DnsRecord> = node.records_of(query_type).map(synthesize).collect();
+            let cname = node.records_of(QueryType::CNAME).next();
+            if answers.is_empty() {
+                if let Some(cname) = cname {
+                    answers.push(synthesize(cname));
+                }
+            }
+
+            if answers.is_empty() {
+                // NODATA, the name exists (maybe only as an empty non-terminal) but not with this type
+                self.negative_answer(&mut packet, signer, |signer| match &encloser {
+                    Some(encloser) => signer.wildcard_proof(&qname, encloser, true),
+                    None => signer.nodata_proof(&qname),
+                });
+                return packet;
+            }
+
+            if let Some(signer) = signer {
+                let signatures = signer.sign(&answers, wildcard.as_ref());
+                if let Some(encloser) = &encloser {
+                    packet
+                        .authorities
+                        .extend(signer.wildcard_proof(&qname, encloser, false));
+                }
+                answers.extend(signatures);
+            }
+
+            let Some(DnsRecord::CNAME { host, .. }) =
+                cname.filter(|_| query_type != QueryType::CNAME)
+            else {
+                packet.resources = self.additional_for(&answers);
+                packet.answers.extend(answers);
+                return packet;
+            };
+
+            let host = host.clone();
+            packet.answers.extend(answers);
+
+            // Targets outside of the zone are left for the client to resolve
+            if !self.contains(&host) {
+                return packet;
+            }
+            qname = host;
+        }
+
+        log::warn!("CNAME chain in zone {} is too long", self.origin);
+        packet
+    }
+}
+
+// All the zones we serve, the most specific one answers
+#[derive(Default)]
+pub struct Zones {
+    zones: Vec<Zone>,
+}
+
+impl Zones {
+    pub fn load(configs: &[ZoneConfig]) -> ZoneResult<Self> {
+        let mut zones = configs
+            .iter()
+            .map(Zone::load)
+            .collect::<ZoneResult<Vec<Zone>>>()?;
+
+        zones.sort_by_key(|zone| std::cmp::Reverse(zone.origin.label_count()));
+
+        Ok(Self { zones })
+    }
+
+    pub fn find(&self, qname: &Name) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.contains(qname))
+    }
+}
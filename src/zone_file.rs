@@ -0,0 +1,667 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::{
+    dns_records::{base32hex_decode, days_from_civil, DnsRecord},
+    errors::{ZoneError, ZoneResult},
+    name::Name,
+    query_class::QueryClass,
+    query_type::QueryType,
+};
+
+// One record or directive, parentheses may spread it over several lines
+struct Entry {
+    line: usize,
+    // Line started with a blank, the owner of the previous record is used
+    owner_omitted: bool,
+    tokens: Vec<Token>,
+}
+
+// Escapes are kept as written, names and strings decode them differently
+struct Token {
+    text: String,
+}
+
+// Reads RFC 1035 master files into records, names keep the case they are written in
+pub struct ZoneFileParser {
+    path: PathBuf,
+    origin: Name,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<Name>,
+    line: usize,
+}
+
+impl ZoneFileParser {
+    pub fn new(path: &Path, origin: &Name) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            origin: origin.clone(),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            line: 0,
+        }
+    }
+
+    pub fn parse(mut self, content: &str) -> ZoneResult<Vec<DnsRecord>> {
+        let mut records = Vec::new();
+
+        for entry in self.tokenize(content)? {
+            self.line = entry.line;
+
+            if entry.tokens[0].text.starts_with('$') && !entry.owner_omitted {
+                self.directive(&entry.tokens)?;
+            } else {
+                records.push(self.record(entry)?);
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn syntax_error(&self, reason: impl Into<String>) -> ZoneError {
+        ZoneError::Syntax {
+            path: self.path.clone(),
+            line: self.line,
+            reason: reason.into(),
+        }
+    }
+
+    fn tokenize(&mut self, content: &str) -> ZoneResult<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut tokens = Vec::new();
+        let mut chars = content.chars().peekable();
+
+        let mut line = 1;
+        let mut entry_line = 1;
+        let mut depth = 0;
+        let mut line_start = true;
+        let mut starts_blank = false;
+
+        while let Some(c) = chars.next() {
+            if line_start {
+                starts_blank = c == ' ' || c == '\t';
+                line_start = false;
+            }
+
+            match c {
+                '\n' => {
+                    line += 1;
+
+                    if depth == 0 {
+                        if !tokens.is_empty() {
+                            entries.push(Entry {
+                                line: entry_line,
+                                owner_omitted: starts_blank,
+                                tokens: std::mem::take(&mut tokens),
+                            });
+                        }
+                        line_start = true;
+                    }
+                }
+                ' ' | '\t' | '\r' => {}
+                ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        self.line = line;
+                        return Err(self.syntax_error("unbalanced ')'"));
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut text = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => {
+                                text.push('\\');
+                                if let Some(escaped) = chars.next() {
+                                    text.push(escaped);
+                                }
+                            }
+                            Some('\n') => {
+                                line += 1;
+                                text.push('\n');
+                            }
+                            Some(c) => text.push(c),
+                            None => {
+                                self.line = line;
+                                return Err(self.syntax_error("unterminated quoted string"));
+                            }
+                        }
+                    }
+
+                    if tokens.is_empty() {
+                        entry_line = line;
+                    }
+                    tokens.push(Token { text });
+                }
+                _ => {
+                    let mut text = c.to_string();
+
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            text.push(escaped);
+                        }
+                    }
+
+                    while let Some(c) = chars.next_if(|c| !" \t\r\n;()\"".contains(*c)) {
+                        text.push(c);
+                        if c == '\\' {
+                            if let Some(escaped) = chars.next() {
+                                text.push(escaped);
+                            }
+                        }
+                    }
+
+                    if tokens.is_empty() {
+                        entry_line = line;
+                    }
+                    tokens.push(Token { text });
+                }
+            }
+        }
+
+        if depth > 0 {
+            self.line = entry_line;
+            return Err(self.syntax_error("unbalanced '('"));
+        }
+
+        if !tokens.is_empty() {
+            entries.push(Entry {
+                line: entry_line,
+                owner_omitted: starts_blank,
+                tokens,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn directive(&mut self, tokens: &[Token]) -> ZoneResult<()> {
+        let name = tokens[0].text.to_uppercase();
+        let value = tokens
+            .get(1)
+            .ok_or_else(|| self.syntax_error(format!("{} expects a value", name)))?;
+
+        match name.as_str() {
+            "$ORIGIN" => self.origin = self.absolute_name(&value.text)?,
+            "$TTL" => self.default_ttl = Some(self.ttl(&value.text)?),
+            _ => return Err(self.syntax_error(format!("unsupported directive {}", name))),
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, entry: Entry) -> ZoneResult<DnsRecord> {
+        let mut tokens = entry.tokens.iter().peekable();
+
+        let owner = if entry.owner_omitted {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| self.syntax_error("first record has no owner"))?
+        } else {
+            let owner = tokens.next().unwrap();
+            self.absolute_name(&owner.text)?
+        };
+        self.last_owner = Some(owner.clone());
+
+        // TTL and class are both optional and may come in either order
+        let mut ttl = None;
+        for _ in 0..2 {
+            let Some(token) = tokens.peek() else {
+                break;
+            };
+
+            if let Some(class) = QueryClass::from_name(&token.text) {
+                if class != QueryClass::IN {
+                    return Err(self.syntax_error(format!(
+                        "only class IN is served from zones, got {}",
+                        class
+                    )));
+                }
+                tokens.next();
+            } else if token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(self.ttl(&token.text)?);
+                tokens.next();
+            }
+        }
+
+        let type_token = tokens
+            .next()
+            .ok_or_else(|| self.syntax_error("record has no type"))?;
+        let query_type = QueryType::from_name(&type_token.text).ok_or_else(|| {
+            self.syntax_error(format!("unsupported record type {}", type_token.text))
+        })?;
+
+        let ttl = match ttl {
+            Some(ttl) => {
+                self.last_ttl = Some(ttl);
+                ttl
+            }
+            // RFC 2308 $TTL, or the TTL of the previous record like RFC 1035 had it
+            None => self
+                .default_ttl
+                .or(self.last_ttl)
+                .ok_or_else(|| self.syntax_error("record has no TTL and there is no $TTL"))?,
+        };
+
+        let rdata: Vec<&Token> = tokens.collect();
+        self.rdata(owner, QueryClass::IN, query_type, ttl, &rdata)
+    }
+
+    fn rdata(
+        &self,
+        domain: Name,
+        class: QueryClass,
+        query_type: QueryType,
+        ttl: u32,
+        rdata: &[&Token],
+    ) -> ZoneResult<DnsRecord> {
+        // RFC 3597 generic form works for every type, known ones end up as their typed record
+        if rdata.first().is_some_and(|token| token.text == "\\#") {
+            return self.generic_rdata(domain, class, query_type, ttl, &rdata[1..]);
+        }
+
+        let expected = match query_type {
+            QueryType::A | QueryType::AAAA | QueryType::NS | QueryType::CNAME | QueryType::PTR => {
+                Some(1)
+            }
+            QueryType::MX => Some(2),
+            QueryType::CAA => Some(3),
+            QueryType::NAPTR => Some(6),
+            QueryType::SRV => Some(4),
+            QueryType::SOA => Some(7),
+            _ => None,
+        };
+
+        match expected {
+            Some(count) if rdata.len() != count => {
+                return Err(self.syntax_error(format!(
+                    "{} expects {} fields, got {}",
+                    query_type,
+                    count,
+                    rdata.len()
+                )));
+            }
+            None if rdata.is_empty() => {
+                return Err(self.syntax_error(format!("{} record has no data", query_type)));
+            }
+            _ => {}
+        }
+
+        let text = |idx: usize| rdata[idx].text.as_str();
+
+        let record = match query_type {
+            QueryType::A => DnsRecord::A {
+                domain,
+                class,
+                addr: self.number::<Ipv4Addr>(text(0), "IPv4 address")?,
+                ttl,
+            },
+            QueryType::AAAA => DnsRecord::AAAA {
+                domain,
+                class,
+                addr: self.number::<Ipv6Addr>(text(0), "IPv6 address")?,
+                ttl,
+            },
+            QueryType::NS => DnsRecord::NS {
+                domain,
+                class,
+                host: self.absolute_name(text(0))?,
+                ttl,
+            },
+            QueryType::CNAME => DnsRecord::CNAME {
+                domain,
+                class,
+                host: self.absolute_name(text(0))?,
+                ttl,
+            },
+            QueryType::PTR => DnsRecord::PTR {
+                domain,
+                class,
+                host: self.absolute_name(text(0))?,
+                ttl,
+            },
+            QueryType::MX => DnsRecord::MX {
+                domain,
+                class,
+                priority: self.number(text(0), "preference")?,
+                host: self.absolute_name(text(1))?,
+                ttl,
+            },
+            QueryType::SRV => DnsRecord::SRV {
+                domain,
+                class,
+                priority: self.number(text(0), "priority")?,
+                weight: self.number(text(1), "weight")?,
+                port: self.number(text(2), "port")?,
+                host: self.absolute_name(text(3))?,
+                ttl,
+            },
+            QueryType::SOA => DnsRecord::SOA {
+                domain,
+                class,
+                m_name: self.absolute_name(text(0))?,
+                r_name: self.absolute_name(text(1))?,
+                serial: self.number(text(2), "serial")?,
+                refresh: self.ttl(text(3))?,
+                retry: self.ttl(text(4))?,
+                expire: self.ttl(text(5))?,
+                minimum: self.ttl(text(6))?,
+                ttl,
+            },
+            QueryType::TXT => DnsRecord::TXT {
+                domain,
+                class,
+                data: rdata
+                    .iter()
+                    .map(|token| self.character_string(token))
+                    .collect::<ZoneResult<_>>()?,
+                ttl,
+            },
+            QueryType::NAPTR => DnsRecord::NAPTR {
+                domain,
+                class,
+                order: self.number(text(0), "order")?,
+                preference: self.number(text(1), "preference")?,
+                flags: self.character_string(rdata[2])?,
+                services: self.character_string(rdata[3])?,
+                regexp: self.character_string(rdata[4])?,
+                replacement: self.absolute_name(text(5))?,
+                ttl,
+            },
+            // The fingerprint may be split into several hex chunks
+            QueryType::SSHFP if rdata.len() >= 3 => DnsRecord::SSHFP {
+                domain,
+                class,
+                algorithm: self.number(text(0), "algorithm")?,
+                fingerprint_type: self.number(text(1), "fingerprint type")?,
+                fingerprint: self.hex(&rdata[2..])?,
+                ttl,
+            },
+            QueryType::CAA => DnsRecord::CAA {
+                domain,
+                class,
+                flags: self.number(text(0), "flags")?,
+                tag: text(1).to_lowercase(),
+                value: self.character_string(rdata[2])?,
+                ttl,
+            },
+            // Digests and keys may be split into several chunks too
+            QueryType::DS if rdata.len() >= 4 => DnsRecord::DS {
+                domain,
+                class,
+                key_tag: self.number(text(0), "key tag")?,
+                algorithm: self.number(text(1), "algorithm")?,
+                digest_type: self.number(text(2), "digest type")?,
+                digest: self.hex(&rdata[3..])?,
+                ttl,
+            },
+            QueryType::DNSKEY if rdata.len() >= 4 => DnsRecord::DNSKEY {
+                domain,
+                class,
+                flags: self.number(text(0), "flags")?,
+                protocol: self.number(text(1), "protocol")?,
+                algorithm: self.number(text(2), "algorithm")?,
+                public_key: self.base64(&rdata[3..])?,
+                ttl,
+            },
+            QueryType::RRSIG if rdata.len() >= 9 => DnsRecord::RRSIG {
+                domain,
+                class,
+                type_covered: QueryType::from_name(text(0)).ok_or_else(|| {
+                    self.syntax_error(format!("unsupported record type {}", text(0)))
+                })?,
+                algorithm: self.number(text(1), "algorithm")?,
+                labels: self.number(text(2), "labels")?,
+                original_ttl: self.ttl(text(3))?,
+                expiration: self.timestamp(text(4))?,
+                inception: self.timestamp(text(5))?,
+                key_tag: self.number(text(6), "key tag")?,
+                signer_name: self.absolute_name(text(7))?,
+                signature: self.base64(&rdata[8..])?,
+                ttl,
+            },
+            QueryType::NSEC => DnsRecord::NSEC {
+                domain,
+                class,
+                next_domain: self.absolute_name(text(0))?,
+                types: self.types(&rdata[1..])?,
+                ttl,
+            },
+            QueryType::NSEC3 if rdata.len() >= 5 => DnsRecord::NSEC3 {
+                domain,
+                class,
+                hash_algorithm: self.number(text(0), "hash algorithm")?,
+                flags: self.number(text(1), "flags")?,
+                iterations: self.number(text(2), "iterations")?,
+                salt: match text(3) {
+                    "-" => Vec::new(),
+                    _ => self.hex(&rdata[3..4])?,
+                },
+                next_hashed: base32hex_decode(text(4).as_bytes())
+                    .ok_or_else(|| self.syntax_error(format!("invalid base32hex {}", text(4))))?,
+                types: self.types(&rdata[5..])?,
+                ttl,
+            },
+            _ => {
+                return Err(self.syntax_error(format!(
+                    "{} records can't be loaded from zone files, use the \\# form",
+                    query_type
+                )))
+            }
+        };
+
+        Ok(record)
+    }
+
+    fn generic_rdata(
+        &self,
+        domain: Name,
+        class: QueryClass,
+        query_type: QueryType,
+        ttl: u32,
+        rdata: &[&Token],
+    ) -> ZoneResult<DnsRecord> {
+        let Some((len, hex)) = rdata.split_first() else {
+            return Err(self.syntax_error("\\# expects the RDATA length"));
+        };
+
+        let len: usize = self.number(&len.text, "RDATA length")?;
+        let data = self.hex(hex)?;
+        if data.len() != len {
+            return Err(self.syntax_error(format!(
+                "RDATA is {} bytes long, {} announced",
+                data.len(),
+                len
+            )));
+        }
+
+        if query_type == QueryType::OPT {
+            return Err(self.syntax_error("OPT records can't be loaded from zone files"));
+        }
+
+        DnsRecord::from_rdata(&domain, class, query_type, ttl, &data)
+            .map_err(|e| self.syntax_error(format!("invalid {} RDATA - {}", query_type, e)))
+    }
+
+    fn absolute_name(&self, name: &str) -> ZoneResult<Name> {
+        if name == "@" {
+            return Ok(self.origin.clone());
+        }
+
+        let (name, absolute) = Name::parse_relative(name)
+            .map_err(|e| self.syntax_error(format!("invalid name - {}", e)))?;
+        if absolute {
+            return Ok(name);
+        }
+
+        name.append(&self.origin)
+            .map_err(|e| self.syntax_error(format!("invalid name - {}", e)))
+    }
+
+    fn number<T: FromStr>(&self, value: &str, what: &str) -> ZoneResult<T> {
+        value
+            .parse()
+            .map_err(|_| self.syntax_error(format!("invalid {} {}", what, value)))
+    }
+
+    // Plain seconds or BIND style units, e.g. 1h30m
+    fn ttl(&self, value: &str) -> ZoneResult<u32> {
+        if let Ok(seconds) = value.parse() {
+            return Ok(seconds);
+        }
+
+        let invalid = || self.syntax_error(format!("invalid TTL {}", value));
+        let mut total: u32 = 0;
+        let mut amount: Option<u32> = None;
+
+        for c in value.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                amount = Some(
+                    amount
+                        .unwrap_or(0)
+                        .checked_mul(10)
+                        .and_then(|amount| amount.checked_add(digit))
+                        .ok_or_else(invalid)?,
+                );
+                continue;
+            }
+
+            let unit = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+
+            total = amount
+                .take()
+                .ok_or_else(invalid)?
+                .checked_mul(unit)
+                .and_then(|seconds| total.checked_add(seconds))
+                .ok_or_else(invalid)?;
+        }
+
+        if amount.is_some() {
+            return Err(invalid());
+        }
+
+        Ok(total)
+    }
+
+    fn hex(&self, tokens: &[&Token]) -> ZoneResult<Vec<u8>> {
+        let digits: String = tokens.iter().map(|token| token.text.as_str()).collect();
+        let invalid = || self.syntax_error(format!("invalid hex {}", digits));
+
+        if !digits.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+
+        (0..digits.len())
+            .step_by(2)
+            .map(|idx| {
+                digits
+                    .get(idx..idx + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(invalid)
+            })
+            .collect()
+    }
+
+    fn base64(&self, tokens: &[&Token]) -> ZoneResult<Vec<u8>> {
+        let text: String = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        BASE64
+            .decode(&text)
+            .map_err(|_| self.syntax_error(format!("invalid base64 {}", text)))
+    }
+
+    // Seconds since the epoch or YYYYMMDDHHmmSS in UTC (RFC 4034 section 3.2)
+    fn timestamp(&self, value: &str) -> ZoneResult<u32> {
+        let invalid = || self.syntax_error(format!("invalid timestamp {}", value));
+
+        if value.len() != 14 {
+            return self.number(value, "timestamp");
+        }
+
+        let field = |range: std::ops::Range<usize>| -> ZoneResult<u32> {
+            value
+                .get(range)
+                .and_then(|digits| digits.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let days = days_from_civil(field(0..4)? as i64, field(4..6)?, field(6..8)?);
+        let seconds = days * 86400
+            + field(8..10)? as i64 * 3600
+            + field(10..12)? as i64 * 60
+            + field(12..14)? as i64;
+
+        u32::try_from(seconds).map_err(|_| invalid())
+    }
+
+    fn types(&self, tokens: &[&Token]) -> ZoneResult<Vec<QueryType>> {
+        tokens
+            .iter()
+            .map(|token| {
+                QueryType::from_name(&token.text).ok_or_else(|| {
+                    self.syntax_error(format!("unsupported record type {}", token.text))
+                })
+            })
+            .collect()
+    }
+
+    // Decodes \X and \DDD escapes of a <character-string>
+    fn character_string(&self, token: &Token) -> ZoneResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut chars = token.text.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+
+            match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let digits: String = [Some(d), chars.next(), chars.next()]
+                        .into_iter()
+                        .flatten()
+                        .collect();
+                    let value = digits
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|_| digits.len() == 3)
+                        .ok_or_else(|| self.syntax_error(format!("invalid escape \\{}", digits)))?;
+                    bytes.push(value);
+                }
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                None => return Err(self.syntax_error("dangling escape")),
+            }
+        }
+
+        if bytes.len() > 255 {
+            return Err(self.syntax_error(format!(
+                "string is {} bytes long, at most 255 are allowed",
+                bytes.len()
+            )));
+        }
+
+        Ok(bytes)
+    }
+}